@@ -0,0 +1,9 @@
+extern crate mio;
+#[macro_use] extern crate log;
+extern crate serial;
+extern crate byteorder;
+extern crate libc;
+
+pub mod zigbee_modem;
+pub mod serial_protocols;
+pub mod zigbee_serial_port;
@@ -1,34 +1,39 @@
-extern crate mio;
 #[macro_use] extern crate log;
 extern crate env_logger;
-extern crate serial;
-extern crate byteorder;
-#[macro_use] extern crate lazy_static;
-extern crate libc;
-
-mod zigbee_modem;
-mod serial_protocols; //.serial_port_parser;
-mod zigbee_serial_port;
+extern crate zigbee;
 
 
 use std::env;
-//use develco_zigbee_modem_protocol::*;
-use zigbee_modem::ZigbeeModem;
-use serial_protocols::mmb_networks_modem_protocol::MmbZigbeeModemProtocol;
+use zigbee::zigbee_modem::ZigbeeModem;
+use zigbee::serial_protocols::mmb_networks_modem_protocol::MmbZigbeeModemProtocol;
+use zigbee::serial_protocols::develco_zigbee_modem_protocol::DevelcoZigbeeModemProtocol;
 
 fn usage(program_name : String) -> String{
     println!("Usage:");
-    println!("{} <zigbee_device> ", program_name);
-    println!("e.g: {} /dev/ttyUSB0", program_name);
+    println!("{} <zigbee_device> [mmb|develco]", program_name);
+    println!("e.g: {} /dev/ttyUSB0 develco", program_name);
+    println!("The protocol defaults to mmb");
     std::process::exit(-1);
 }
 
 fn main() {
     let zigbee_device_name = match env::args().nth(1) {
-        None         => usage(env::args().nth(0).unwrap()),
+        None         => usage(env::args().next().unwrap()),
         Some(device) => device
     };
-    env_logger::init().ok().expect("Error initializing loggger");
-    let mut zigbee_device = zigbee_modem::ZigbeeModem::<MmbZigbeeModemProtocol>::new(zigbee_device_name, MmbZigbeeModemProtocol::new());
-    zigbee_device.run();
+    env_logger::init().expect("Error initializing loggger");
+    match env::args().nth(2).as_deref() {
+        None | Some("mmb") => {
+            let mut zigbee_device = ZigbeeModem::<MmbZigbeeModemProtocol>::new(zigbee_device_name, MmbZigbeeModemProtocol::new());
+            zigbee_device.run(|_, event| info!("{:?}", event));
+        },
+        Some("develco") => {
+            let mut zigbee_device = ZigbeeModem::<DevelcoZigbeeModemProtocol>::new(zigbee_device_name, DevelcoZigbeeModemProtocol::new());
+            zigbee_device.parser_mut().request_protocol_version().expect("Error requesting the modem protocol version");
+            zigbee_device.run(|_, event| info!("{:?}", event));
+        },
+        Some(_) => {
+            usage(env::args().next().unwrap());
+        }
+    }
 }
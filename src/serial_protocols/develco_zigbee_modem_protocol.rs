@@ -1,11 +1,21 @@
-extern crate byteorder;
-
-use serial_port_parser::SerialPortParser;
+use serial_protocols::serial_port_parser::SerialPortParser;
 use std::fmt;
-use std::mem;
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::Cursor;
-use std::ops;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Error, ErrorKind, Write};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use zigbee_serial_port::ZigbeeSerialPort;
+
+// Frames: [Start of frame: 1][Length: 2][Header: 1][Body], the length counting the header and
+// the body.
+const START_OF_FRAME: u8 = 0x02;
+const FRAMING_SIZE: usize = 3;
+const HEADER_OFFSET: usize = 3;
+// Longest header plus body we accept. A message carries at most one Zigbee frame (127 bytes
+// over the air) plus its addressing fields, so it always fits in 255 bytes and a longer
+// length is a corrupted one or a stray START_OF_FRAME.
+const MAX_FRAME_LENGTH: usize = 0xFF;
 
 
 enum HeaderFields {
@@ -17,18 +27,86 @@ enum HeaderFields {
 	IsNormalOrBypass =		0b00100000,
 }
 
-enum HeaderMessageTypes {
-	GenericDataInOut =		0b00000,
-	ZdoZdp = 				0b00001,
-	TrustCenterAuthDevice = 0b00010,
-	TrustCenterGetEntry =	0b00011,
-	RegisterEndPoint =		0b00100,
-	InterPan =				0b00101,
-	EspBackend = 			0b10000,
-	UartTunnel =			0b11100,
-	DevUtilsLite =			0b11101,
-	DeviceConfig =			0b11110,
-	ProtocolVersion =		0b11111
+// Bits 1 to 5 (base 1) of the header
+const MESSAGE_GROUP_MASK: u8 = 0b00011111;
+
+byte_enum! {
+	pub enum HeaderMessageTypes {
+		GenericDataInOut =		0b00000,
+		ZdoZdp = 				0b00001,
+		TrustCenterAuthDevice = 0b00010,
+		TrustCenterGetEntry =	0b00011,
+		RegisterEndPoint =		0b00100,
+		InterPan =				0b00101,
+		EspBackend = 			0b10000,
+		UartTunnel =			0b11100,
+		DevUtilsLite =			0b11101,
+		DeviceConfig =			0b11110,
+		ProtocolVersion =		0b11111
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+	Command,
+	Response
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+	FromHost,
+	FromModem
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageMode {
+	Normal,
+	Bypass
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DevelcoHeader {
+	pub kind: MessageKind,
+	pub direction: Direction,
+	pub mode: MessageMode,
+	pub group: HeaderMessageTypes
+}
+
+impl DevelcoHeader {
+	pub fn new(kind: MessageKind, direction: Direction, mode: MessageMode, group: HeaderMessageTypes) -> DevelcoHeader {
+		DevelcoHeader {
+			kind,
+			direction,
+			mode,
+			group
+		}
+	}
+
+	pub fn to_u8(self) -> u8 {
+		let mut header = u8::from(self.group) & MESSAGE_GROUP_MASK;
+		if self.kind == MessageKind::Response {
+			header |= HeaderFields::IsResponseOrCommand as u8;
+		}
+		if self.direction == Direction::FromModem {
+			header |= HeaderFields::FromModemOrHost as u8;
+		}
+		if self.mode == MessageMode::Bypass {
+			header |= HeaderFields::IsNormalOrBypass as u8;
+		}
+		header
+	}
+}
+
+impl From<u8> for DevelcoHeader {
+	fn from(header: u8) -> DevelcoHeader {
+		let is_set = |field: HeaderFields| header & field as u8 != 0;
+		DevelcoHeader {
+			kind: if is_set(HeaderFields::IsResponseOrCommand) { MessageKind::Response } else { MessageKind::Command },
+			direction: if is_set(HeaderFields::FromModemOrHost) { Direction::FromModem } else { Direction::FromHost },
+			mode: if is_set(HeaderFields::IsNormalOrBypass) { MessageMode::Bypass } else { MessageMode::Normal },
+			group: HeaderMessageTypes::from(header & MESSAGE_GROUP_MASK)
+		}
+	}
 }
 
 /*
@@ -41,102 +119,72 @@ enum ModemMessages {
 	UartTunnel =				0b11100
 }*/
 
-enum MessageTypes {
-	GenericDataOutMsg =			0x00,
-	GenericDataOutConfirm =		0x80,
-	ZdoZdpReq =					0x01,
-	ZdoZdpRes =					0x81,
-	TrustCenterAuthDeviceReq =	0x02,
-	TrustCenterAuthDeviceRes =	0x82,
-	TrustCenterGetEntryReq = 	0x03,
-	TrustCenterGetEntryRes =	0x83,
-	RegisterEndPointReq =		0x04,
-	DeregisterEndPointReq =		0x88,
-	InterPanMsg =				0x05,
-	InterPanConfirm =			0x85,
-	BackupRestorePanReq =		0x0a,
-	BackupRestorePanRes =		0x8a,
-	BackupEntryReq =			0x0b,
-	BackupEntryRes =			0x8b,
-	// ...
-	GenericDataInMsg =			0x40
-}
-
-impl From<u8> for MessageTypes {
-	fn from(num: u8) -> MessageTypes {
-		unsafe{ mem::transmute(num) }
+byte_enum! {
+	pub enum AddressMode {
+		Indirect =	0x00,
+		Group =		0x01,
+		Network =	0x02,
+		Eui =		0x03
 	}
 }
 
-enum AddressMode {
-	Indirect =	0x00,
-	Group =		0x01,
-	Network =	0x02,
-	Eui =		0x03
-}
-
-impl From<u8> for AddressMode {
-	fn from(num: u8) -> AddressMode {
-		unsafe{ mem::transmute(num) }
-	}
-}
-
-enum AddressLength {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressLength {
 	Zero,
 	TwoBytes{ address: u16 },
-	FourBytes{ address: u64 }
+	EightBytes{ address: u64 }
 }
 
 impl AddressMode{
-	fn address_length_type( mode: AddressMode ) -> AddressLength {
+	// We can't know where the rest of the message starts after an unknown address mode, so
+	// there is no address to read (None)
+	fn read_address( mode: AddressMode, cursor: &mut Cursor<&[u8]> ) -> Result<Option<AddressLength>, DevelcoZigbeeModemError> {
 		match mode {
-			AddressMode::Indirect => AddressLength::Zero,
-			AddressMode::Group | AddressMode::Network => AddressLength::TwoBytes{address: 0},
-			AddressMode::Eui => AddressLength::FourBytes{address: 0}
+			AddressMode::Indirect => Ok(Some(AddressLength::Zero)),
+			AddressMode::Group | AddressMode::Network =>
+				Ok(Some(AddressLength::TwoBytes{address: cursor.read_u16::<LittleEndian>().map_err(DevelcoZigbeeModemError::truncated)?})),
+			AddressMode::Eui =>
+				Ok(Some(AddressLength::EightBytes{address: cursor.read_u64::<LittleEndian>().map_err(DevelcoZigbeeModemError::truncated)?})),
+			AddressMode::Unknown(_) => Ok(None)
 		}
 	}
-
-	fn address_length( mode: AddressMode ) -> usize {
-		match mode {
-			AddressMode::Indirect => 0,
-			AddressMode::Group | AddressMode::Network => 2,
-			AddressMode::Eui => 8
-		}
-	}
-
 }
 
 
 // TODO Lo mismo no son tan comunes.... por lo visto cambiar el orden de los campos en funcion
 // del tipo de mensaje
-struct CommonMsgFields<T,S>{
-	msg_type: u8,
-	destination_address_mode: u8,
-	destination_address: T,
-	destination_endpoint: u8,
-	source_address_mode: u8,
-	source_address: S,
-	source_endpoint: u8,
-	profile_id: u16, // little endian
-	cluster_id: u16, // little endian
-	link_quality: u8,
-	was_broadcast: u8,
-	security_status: u8
+#[derive(Debug)]
+pub struct CommonMsgFields<T,S>{
+	pub msg_type: u8,
+	pub destination_address_mode: u8,
+	pub destination_address: T,
+	pub destination_endpoint: u8,
+	pub source_address_mode: u8,
+	pub source_address: S,
+	pub source_endpoint: u8,
+	pub profile_id: u16, // little endian
+	pub cluster_id: u16, // little endian
+	pub link_quality: u8,
+	pub was_broadcast: u8,
+	pub security_status: u8
 }
 
-struct CommonMsgFields2<T>{
-	msg_type: u8,
-	destination_address_mode: u8,
-	destination_address: T,
-	profile_id: u16, // little endian
-	destination_endpoint: u8,
-	cluster_id: u16, // little endian
-	source_endpoint: u8,
-	tx_options: u8
+#[derive(Debug)]
+pub struct CommonMsgFields2<T>{
+	pub msg_type: u8,
+	pub destination_address_mode: u8,
+	pub destination_address: T,
+	pub profile_id: u16, // little endian
+	pub destination_endpoint: u8,
+	pub cluster_id: u16, // little endian
+	pub source_endpoint: u8,
+	pub tx_options: u8
 }
 
-enum MessageBody {
-	Nothing,
+#[derive(Debug)]
+pub enum MessageBody {
+	// The body of the messages we don't decode yet
+	Undecoded(Vec<u8>),
 	GenericDataInMsg {
 		common_fields: CommonMsgFields<AddressLength,AddressLength>,
 		asdu_length: u8, /* Payload length */
@@ -149,155 +197,370 @@ enum MessageBody {
 	},
 }
 
+
 pub struct DevelcoZigbeeModemMessage {
-	header: Vec<u8>,
-	body: MessageBody
+	pub header: DevelcoHeader,
+	pub body: MessageBody
 }
 
-impl <'a> DevelcoZigbeeModemMessage {
-	/*pub fn new(buff: Vec<u8>) -> Result<DevelcoZigbeeModemMessage, DevelcoZigbeeModemError<'a>> {
-		match MessageTypes::from(buff[3]) {
-			MessageTypes::GenericDataInMsg => {
-				let destination_address_length = AddressMode::address_length(AddressMode::from(buff[5]));
-				let source_address_length = AddressMode::address_length(AddressMode::from(buff[8+destination_address_length]));
-				let common_fields = CommonMsgFields {
-					msg_type: buff[3],
-					destination_address_mode: buff[4],
-					destination_address: match destination_address_length {
-						8 => Cursor::new(&buff[5..5+destination_address_length]).read_u64::<LittleEndian>().unwrap(),
-						2 | _ => Cursor::new(&buff[5..5+destination_address_length]).read_u16::<LittleEndian>().unwrap() as u64
-					},
-					destination_endpoint: buff[6 + dErr(DevelcoZigbeeModemError{
-					error: "Unknown message type!"
-				})estination_address_length],
-					source_address_mode: buff[7 + destination_address_length],
-					source_address: match source_address_length {
-						8 =>  Cursor::new(&buff[9+destination_address_length..9+destination_address_length+source_address_length])
-								.read_u64::<LittleEndian>().unwrap(),
-						2 | _ =>  Cursor::new(&buff[9+destination_address_length..9+destination_address_length+source_address_length])
-									.read_u16::<LittleEndian>().unwrap() as u64
-					},
-					source_endpoint: buff[9 + destination_address_length+source_address_length],
-					profile_id: Cursor::new(&buff[10+destination_address_length+source_address_length..12+destination_address_length+source_address_length])
-								  .read_u16::<LittleEndian>().unwrap(),
-					cluster_id: Cursor::new(&buff[10+destination_address_length+source_address_length..12+destination_address_length+source_address_length])
-								  .read_u16::<LittleEndian>().unwrap(),
-					link_quality: buff[14 + destination_address_length+source_address_length],
-					was_broadcast: buff[15 + destination_address_length+source_address_length],
-					security_status: buff[16 + destination_address_length+source_address_length]
-				};
+impl DevelcoZigbeeModemMessage {
+	// `buff` is a whole frame, framing bytes included
+	pub fn new(buff: &[u8]) -> Result<DevelcoZigbeeModemMessage, DevelcoZigbeeModemError> {
+		if buff.len() <= HEADER_OFFSET {
+			return Err(DevelcoZigbeeModemError::new("Message too short!"));
+		}
+		let mut cursor = Cursor::new(&buff[HEADER_OFFSET..]);
+		let msg_type = read_u8(&mut cursor)?;
+		let header = DevelcoHeader::from(msg_type);
+		let body = match Self::read_body(header, msg_type, &mut cursor)? {
+			Some(body) => body,
+			None => MessageBody::Undecoded(buff[HEADER_OFFSET + 1..].to_vec())
+		};
 
-				let body = MessageBody::GenericDataInMsg{
-					common_fields: common_fields,
-					asdu_length: buff[17 + destination_address_length+source_address_length],
-					asdu: buff[18+destination_address_length+source_address_length..].to_vec()
-				};
+		Ok(DevelcoZigbeeModemMessage {
+			header,
+			body
+		})
+	}
 
-				Ok(DevelcoZigbeeModemMessage {
-					header: buff[0..4].to_vec(),
-					body: body
-				})
+	// None if we don't know how to decode the body
+	fn read_body(header: DevelcoHeader, msg_type: u8, cursor: &mut Cursor<&[u8]>) -> Result<Option<MessageBody>, DevelcoZigbeeModemError> {
+		let body = match (header.group, header.kind, header.direction, header.mode) {
+			(HeaderMessageTypes::GenericDataInOut, MessageKind::Command, Direction::FromModem, MessageMode::Normal) => {
+				let destination_address_mode = read_u8(cursor)?;
+				let destination_address = match AddressMode::read_address(AddressMode::from(destination_address_mode), cursor)? {
+					Some(address) => address,
+					None => return Ok(None)
+				};
+				let destination_endpoint = read_u8(cursor)?;
+				let source_address_mode = read_u8(cursor)?;
+				let source_address = match AddressMode::read_address(AddressMode::from(source_address_mode), cursor)? {
+					Some(address) => address,
+					None => return Ok(None)
+				};
+				let common_fields = CommonMsgFields {
+					msg_type,
+					destination_address_mode,
+					destination_address,
+					destination_endpoint,
+					source_address_mode,
+					source_address,
+					source_endpoint: read_u8(cursor)?,
+					profile_id: read_u16(cursor)?,
+					cluster_id: read_u16(cursor)?,
+					link_quality: read_u8(cursor)?,
+					was_broadcast: read_u8(cursor)?,
+					security_status: read_u8(cursor)?
+				};
+				let asdu_length = read_u8(cursor)?;
+				MessageBody::GenericDataInMsg {
+					common_fields,
+					asdu_length,
+					asdu: read_bytes(cursor, asdu_length as usize)?
+				}
 			},
-
-			MessageTypes::GenericDataOutMsg => {
-				let body = MessageBody::Nothing{};
-				Ok(DevelcoZigbeeModemMessage {
-					header: buff[0..4].to_vec(),
-					body: body
-				})
+			(HeaderMessageTypes::GenericDataInOut, MessageKind::Command, Direction::FromHost, MessageMode::Normal) => {
+				let destination_address_mode = read_u8(cursor)?;
+				let destination_address = match AddressMode::read_address(AddressMode::from(destination_address_mode), cursor)? {
+					Some(address) => address,
+					None => return Ok(None)
+				};
+				let common_fields = CommonMsgFields2 {
+					msg_type,
+					destination_address_mode,
+					destination_address,
+					profile_id: read_u16(cursor)?,
+					destination_endpoint: read_u8(cursor)?,
+					cluster_id: read_u16(cursor)?,
+					source_endpoint: read_u8(cursor)?,
+					tx_options: read_u8(cursor)?
+				};
+				let asdu_length = read_u8(cursor)?;
+				MessageBody::GenericDataOutMsg {
+					common_fields,
+					asdu_length,
+					asdu: read_bytes(cursor, asdu_length as usize)?
+				}
 			},
-			_ => {
-				error!("Unknown message type!");
-				Err(DevelcoZigbeeModemError{
-					error: "Unknown message type!"
-				})
-			}
+			// TODO: Decode the rest of the message types
+			_ => return Ok(None)
 		};
+		Ok(Some(body))
+	}
+}
 
-		Err(DevelcoZigbeeModemError{
-			error: "Unknown message type!"
-		})
-	}*/
-
+fn read_u8(cursor: &mut Cursor<&[u8]>) -> Result<u8, DevelcoZigbeeModemError> {
+	cursor.read_u8().map_err(DevelcoZigbeeModemError::truncated)
+}
 
-	pub fn new(buff: Vec<u8>) -> Result<DevelcoZigbeeModemMessage, DevelcoZigbeeModemError<'a>> {
-		match MessageTypes::from(buff[3]) {
-			MessageTypes::GenericDataInMsg => {
-				let destination_address_length = AddressMode::address_length_type(AddressMode::from(buff[5]));
-				let source_address_length = AddressMode::address_length_type(AddressMode::from(buff[8+mem::size_of::<destination_address>()]));
+fn read_u16(cursor: &mut Cursor<&[u8]>) -> Result<u16, DevelcoZigbeeModemError> {
+	cursor.read_u16::<LittleEndian>().map_err(DevelcoZigbeeModemError::truncated)
+}
 
+fn read_bytes(cursor: &mut Cursor<&[u8]>, len: usize) -> Result<Vec<u8>, DevelcoZigbeeModemError> {
+	let start = cursor.position() as usize;
+	let buff = *cursor.get_ref();
+	if buff.len() < start + len {
+		return Err(DevelcoZigbeeModemError::new("Message shorter than expected!"));
+	}
+	cursor.set_position((start + len) as u64);
+	Ok(buff[start..start + len].to_vec())
+}
 
-				let common_fields : CommonMsgFields<source_address_length, destination_address_length>;
-				let asdu_length = buff[mem::size_of::<common_fields>() + 1];
-				let asdu = vec![asdu_length;0];
-				let msg = MessageBody::GenericDataInMsg{
-					common_fields: common_fields,
-					asdu_length: asdu_length,
-					asdu: asdu
-				};
 
-				unsafe{
-					msg.common_fields = ptr::read(buff.as_ptr() as *const MessageBody::GenericDataInMsg);
-				}
+pub struct DevelcoZigbeeModemError {
+	error: &'static str
+}
 
-			},
-			MessageTypes::GenericDataOutMsg => {
-				let body = MessageBody::Nothing{};
+impl DevelcoZigbeeModemError {
+	fn new(error: &'static str) -> DevelcoZigbeeModemError {
+		DevelcoZigbeeModemError {
+			error
+		}
+	}
 
-				Ok()
-			},
-			_ => {
-				error!("Unknown message type!");
-				Err(DevelcoZigbeeModemError{
-					error: "Unknown message type!"
-				})
-			}
-		};
-		Err(DevelcoZigbeeModemError{
-			error: "Unknown message type!"
-		})
+	fn truncated(_: Error) -> DevelcoZigbeeModemError {
+		DevelcoZigbeeModemError::new("Message shorter than expected!")
 	}
 }
 
+pub struct DevelcoZigbeeModemProtocol {
+	serial_port: Option<Rc<RefCell<ZigbeeSerialPort>>>,
+	// Bytes of a frame that hasn't been completely received yet
+	buffer: Vec<u8>,
+	// Frames received from the modem, until the application polls them
+	events: VecDeque<DevelcoZigbeeModemMessage>
+}
 
-pub struct DevelcoZigbeeModemError<'a>{
-	error: &'a str
+impl Default for DevelcoZigbeeModemProtocol {
+	fn default() -> DevelcoZigbeeModemProtocol {
+		DevelcoZigbeeModemProtocol::new()
+	}
 }
 
-pub struct DevelcoZigbeeModemProtocol<'a> {
-	pub foo: &'a str
+impl DevelcoZigbeeModemProtocol {
+	pub fn new() -> DevelcoZigbeeModemProtocol {
+		DevelcoZigbeeModemProtocol {
+			serial_port: None,
+			buffer: Vec::new(),
+			events: VecDeque::new()
+		}
+	}
+
+	// Splits the buffered bytes into frames, dropping anything before a START_OF_FRAME. A
+	// START_OF_FRAME followed by a length out of range isn't the start of a frame, so it's
+	// dropped too and we look for the next one.
+	fn next_frame(&mut self) -> Option<Vec<u8>> {
+		loop {
+			match self.buffer.iter().position(|&byte| byte == START_OF_FRAME) {
+				Some(start) => { self.buffer.drain(..start); },
+				None => {
+					self.buffer.clear();
+					return None;
+				}
+			}
+			if self.buffer.len() < FRAMING_SIZE {
+				return None;
+			}
+			let length = Cursor::new(&self.buffer[1..FRAMING_SIZE]).read_u16::<LittleEndian>().unwrap_or(0) as usize;
+			if length == 0 || length > MAX_FRAME_LENGTH {
+				self.buffer.remove(0);
+				continue;
+			}
+			if self.buffer.len() < FRAMING_SIZE + length {
+				return None;
+			}
+			return Some(self.buffer.drain(..FRAMING_SIZE + length).collect());
+		}
+	}
+
+	fn process(&mut self, msg: DevelcoZigbeeModemMessage) {
+		let header = msg.header;
+		if header.direction == Direction::FromHost {
+			warn!("Received a frame meant for the modem: {:?}", msg);
+			return;
+		}
+		match (header.mode, header.group, header.kind) {
+			(MessageMode::Bypass, _, _) => trace!("Received bypass frame: {:?}", msg),
+			(_, HeaderMessageTypes::Unknown(group), _) => warn!("Received a frame of unknown message group: {:#x}", group),
+			(_, HeaderMessageTypes::ProtocolVersion, MessageKind::Response) => {
+				match msg.body {
+					MessageBody::Undecoded(ref version) => info!("Modem protocol version: {:?}", version),
+					_ => warn!("Unexpected Protocol Version response: {:?}", msg)
+				}
+			},
+			(_, HeaderMessageTypes::GenericDataInOut, MessageKind::Command) => trace!("Received data: {:?}", msg),
+			// TODO: Handle every message group
+			(_, group, MessageKind::Command) => trace!("Received {:?} command: {:?}", group, msg),
+			(_, group, MessageKind::Response) => trace!("Received {:?} response: {:?}", group, msg)
+		}
+		self.events.push_back(msg);
+	}
+
+	// Asks the modem for the version of the serial protocol it speaks. The modem answers with
+	// a Protocol Version response.
+	pub fn request_protocol_version(&mut self) -> Result<(), Error> {
+		let header = DevelcoHeader::new(MessageKind::Command, Direction::FromHost, MessageMode::Normal,
+			HeaderMessageTypes::ProtocolVersion);
+		self.send(header, &[])
+	}
+
+	fn send(&mut self, header: DevelcoHeader, body: &[u8]) -> Result<(), Error> {
+		let frame = encode_frame(header, body)?;
+		trace!("Sending: {:?} to modem", frame);
+		match self.serial_port {
+			Some(ref fd) => fd.borrow_mut().write_all(&frame),
+			None => Err(Error::other("Serial port to write not found!"))
+		}
+	}
 }
 
-impl <'a> SerialPortParser for DevelcoZigbeeModemProtocol<'a>{
-	type Message = DevelcoZigbeeModemMessage;
-	type Error = DevelcoZigbeeModemError<'a>;
-	fn parse(&self, buff : Vec<u8>)	-> Result<DevelcoZigbeeModemMessage, DevelcoZigbeeModemError<'a>> {
+fn encode_frame(header: DevelcoHeader, body: &[u8]) -> Result<Vec<u8>, Error> {
+	if body.len() + 1 > MAX_FRAME_LENGTH {
+		return Err(Error::new(ErrorKind::InvalidInput, "Message too long for a frame"));
+	}
+	let mut frame = Vec::with_capacity(FRAMING_SIZE + 1 + body.len());
+	frame.push(START_OF_FRAME);
+	frame.write_u16::<LittleEndian>((body.len() + 1) as u16)?;
+	frame.push(header.to_u8());
+	frame.extend_from_slice(body);
+	Ok(frame)
+}
 
-		/********************/
-		for byte in &buff {
-			print!("0x{:X} " , byte);
+impl SerialPortParser for DevelcoZigbeeModemProtocol {
+	type Event = DevelcoZigbeeModemMessage;
+
+	fn parse(&mut self, buff: &[u8]) -> Result<(),()> {
+		self.buffer.extend_from_slice(buff);
+		let mut result = Ok(());
+		while let Some(frame) = self.next_frame() {
+			match DevelcoZigbeeModemMessage::new(&frame) {
+				Ok(msg) => self.process(msg),
+				Err(e) => {
+					error!("Error parsing message from the UART: {:?}", e);
+					result = Err(());
+				}
+			}
 		}
-		println!("END");
-		/*******************/
+		result
+	}
 
-		/*let mut msg = DevelcoZigbeeModemMessage{
-			header: vec![0;3]
-		};
-		msg.header.clone_from_slice(&buff[..3]);*/
+	fn poll_event(&mut self) -> Option<DevelcoZigbeeModemMessage> {
+		self.events.pop_front()
+	}
 
-		let msg = DevelcoZigbeeModemMessage::new(buff);
-		msg
+	fn set_serial_port(&mut self, serial_port: Rc<RefCell<ZigbeeSerialPort>>) {
+		self.serial_port = Some(serial_port);
 	}
 }
 
 impl fmt::Debug for DevelcoZigbeeModemMessage {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "DevelcoModem: [Field][Filed2][AnotherField][][][..]" )
+		write!(f, "DevelcoModem: {:?} {:?}", self.header, self.body)
 	}
 }
 
-impl <'a> fmt::Debug for DevelcoZigbeeModemError<'a> {
+impl fmt::Debug for DevelcoZigbeeModemError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "DevelcoModem: Error!: {}", self.error)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn splits_frames_and_drops_garbage() {
+		let mut protocol = DevelcoZigbeeModemProtocol::new();
+		protocol.buffer.extend_from_slice(&[0xAA, START_OF_FRAME, 0x02, 0x00, 0x5F, 0x01, START_OF_FRAME, 0x01]);
+		assert_eq!(protocol.next_frame(), Some(vec![START_OF_FRAME, 0x02, 0x00, 0x5F, 0x01]));
+		assert_eq!(protocol.next_frame(), None);
+		protocol.buffer.extend_from_slice(&[0x00, 0xDF]);
+		assert_eq!(protocol.next_frame(), Some(vec![START_OF_FRAME, 0x01, 0x00, 0xDF]));
+		assert!(protocol.buffer.is_empty());
+	}
+
+	#[test]
+	fn a_length_out_of_range_does_not_stall_the_next_frames() {
+		let mut protocol = DevelcoZigbeeModemProtocol::new();
+		protocol.buffer.extend_from_slice(&[START_OF_FRAME, 0xFF, 0xFF, START_OF_FRAME, 0x00, 0x00]);
+		protocol.buffer.extend_from_slice(&[START_OF_FRAME, 0x01, 0x00, 0xDF]);
+		assert_eq!(protocol.next_frame(), Some(vec![START_OF_FRAME, 0x01, 0x00, 0xDF]));
+		assert_eq!(protocol.next_frame(), None);
+	}
+
+	#[test]
+	fn header_bytes_decode_and_encode_back() {
+		for byte in 0..=0xFFu8 {
+			assert_eq!(DevelcoHeader::from(byte).to_u8(), byte);
+		}
+		let header = DevelcoHeader::from(0xDF);
+		assert_eq!(header, DevelcoHeader::new(MessageKind::Response, Direction::FromModem, MessageMode::Normal,
+			HeaderMessageTypes::ProtocolVersion));
+		assert_eq!(DevelcoHeader::from(0x26).group, HeaderMessageTypes::Unknown(0x06));
+		assert_eq!(DevelcoHeader::from(0x26).mode, MessageMode::Bypass);
+	}
+
+	#[test]
+	fn encodes_a_protocol_version_request() {
+		let header = DevelcoHeader::new(MessageKind::Command, Direction::FromHost, MessageMode::Normal,
+			HeaderMessageTypes::ProtocolVersion);
+		assert_eq!(encode_frame(header, &[]).unwrap(), [START_OF_FRAME, 0x01, 0x00, 0x1F]);
+		assert!(encode_frame(header, &[0; MAX_FRAME_LENGTH]).is_err());
+	}
+
+	#[test]
+	fn encoded_generic_data_out_decodes_back() {
+		let header = DevelcoHeader::new(MessageKind::Command, Direction::FromHost, MessageMode::Normal,
+			HeaderMessageTypes::GenericDataInOut);
+		// [Address mode][Address: 2][Profile: 2][Endpoint][Cluster: 2][Source endpoint][Tx options][ASDU length][ASDU]
+		let body = [0x02, 0x34, 0x12, 0x04, 0x01, 0x0A, 0x06, 0x00, 0x01, 0x00, 0x03, 0x01, 0x02, 0x03];
+		let msg = DevelcoZigbeeModemMessage::new(&encode_frame(header, &body).unwrap()).unwrap();
+		assert_eq!(msg.header, header);
+		match msg.body {
+			MessageBody::GenericDataOutMsg{ common_fields, asdu_length, asdu } => {
+				assert_eq!(common_fields.destination_address, AddressLength::TwoBytes{ address: 0x1234 });
+				assert_eq!(common_fields.profile_id, 0x0104);
+				assert_eq!(common_fields.destination_endpoint, 0x0A);
+				assert_eq!(common_fields.cluster_id, 0x0006);
+				assert_eq!(common_fields.source_endpoint, 0x01);
+				assert_eq!(asdu_length, 3);
+				assert_eq!(asdu, [0x01, 0x02, 0x03]);
+			},
+			body => panic!("Expected a Generic Data Out message, got {:?}", body)
+		}
+	}
+
+	#[test]
+	fn an_unknown_address_mode_leaves_the_body_undecoded() {
+		let header = DevelcoHeader::new(MessageKind::Command, Direction::FromHost, MessageMode::Normal,
+			HeaderMessageTypes::GenericDataInOut);
+		let body = [0x07, 0x34, 0x12, 0x04, 0x01];
+		let msg = DevelcoZigbeeModemMessage::new(&encode_frame(header, &body).unwrap()).unwrap();
+		assert_eq!(msg.header, header);
+		match msg.body {
+			MessageBody::Undecoded(bytes) => assert_eq!(bytes, body),
+			body => panic!("Expected an undecoded body, got {:?}", body)
+		}
+	}
+
+	#[test]
+	fn frames_from_the_modem_become_events() {
+		let mut protocol = DevelcoZigbeeModemProtocol::new();
+		// A request from the host, a frame of an unknown message group and a Protocol Version response
+		for &header in &[0x1F, 0x46, 0xDF] {
+			protocol.process(DevelcoZigbeeModemMessage::new(&[START_OF_FRAME, 0x03, 0x00, header, 0x01, 0x02]).unwrap());
+		}
+		let msg = protocol.poll_event().unwrap();
+		assert_eq!(msg.header.group, HeaderMessageTypes::Unknown(0x06));
+		assert_eq!(msg.header.to_u8(), 0x46);
+		let msg = protocol.poll_event().unwrap();
+		assert_eq!(msg.header.group, HeaderMessageTypes::ProtocolVersion);
+		match msg.body {
+			MessageBody::Undecoded(version) => assert_eq!(version, [0x01, 0x02]),
+			body => panic!("Expected the protocol version, got {:?}", body)
+		}
+		assert!(protocol.poll_event().is_none());
+	}
+}
@@ -1,154 +1,349 @@
 extern crate byteorder;
 extern crate serial;
 
-use zigbee_modem::ZigbeeModem;
 use serial_protocols::serial_port_parser::SerialPortParser;
 use std::fmt;
-use std::mem;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::Cursor;
-use std::ops;
 use std::collections::HashMap;
-use serial::SerialPort;
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
-use std::rc::Weak;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use zigbee_serial_port::ZigbeeSerialPort;
 
-macro_rules! hashmap {
-    ($( $key: expr => $val: expr ),*) => {{
-         let mut map = HashMap::new();
-         $( map.insert($key, $val); )*
-         map
-    }}
-}
+mod network_commissioning;
+mod security_config;
+mod zigbee_support_config;
+mod zdo_messages;
+mod zcl_messages;
+mod general_clusters;
+mod ha_clusters;
+mod bootloader;
+mod ota_server;
+mod diagnostics;
+
+pub use self::network_commissioning::{NetworkParameters, NetworkStatus, NetworkState, NodeType, DeviceUpdate, DeviceUpdateStatus,
+    JoinPolicy};
+pub use self::security_config::{NetworkKey, KEY_SIZE};
+pub use self::zigbee_support_config::SimpleDescriptor;
+pub use self::zdo_messages::{AddressResponse, NodeDescriptor, PowerDescriptor, MatchDescriptorResponse};
+pub use self::zcl_messages::{Destination, ZclSource, AttributeValue, AttributeKey, ReadAttributeRecord, AttributeStatusRecord,
+    ReportingConfiguration, HOME_AUTOMATION_PROFILE_ID};
+pub use self::general_clusters::{OnOffCommand, LevelDirection, GeneralClusterCommand};
+pub use self::ha_clusters::{SetpointMode, ZoneEnrollResponseCode, ZoneEnrollRequest, ZoneStatusChange, MeterReading};
+pub use self::bootloader::BootloadProgress;
+pub use self::ota_server::{OtaImage, OtaUpgradeEnd};
+pub use self::diagnostics::{DiagnosticCounters, TrafficCounters, NetworkCounters, LinkQuality, NeighborTableEntry,
+    ChildTableEntry};
 
 // https://mmbnetworks.atlassian.net/wiki/display/SPRHA17/Protocol+Architecture
 
-struct MmbZigbeeModemError{
-    error: &'static str
+pub enum MmbZigbeeModemError{
+    Format(&'static str),
+    InvalidChecksum{ expected: u16, received: u16 },
+    Io(Error),
+    Timeout(&'static str),
+    NotAcknowledged{ frame_seq_number: u8 },
+    // The module answered with a Status Response other than SUCCESS...
+    CommandFailed{ waiting_for: &'static str, frame_seq_number: u8, status: StatusCode },
+    // ...or with an Error frame, so it didn't even run the command
+    CommandRejected{ waiting_for: &'static str, frame_seq_number: u8, error: ErrorCode },
+    NotInitialized,
+    ZdoFailed{ cluster_id: u16, status: u8 },
+    // A Default Response with a failure status
+    ZclFailed{ cluster_id: u16, command_id: u8, status: u8 },
+    BootloadFailed(&'static str)
 }
 impl MmbZigbeeModemError{
 	fn new(message: &'static str) -> MmbZigbeeModemError {
 		trace!("{}", message);
-		MmbZigbeeModemError{
-			error: message
-		}
+		MmbZigbeeModemError::Format(message)
 	}
 }
 impl fmt::Debug for MmbZigbeeModemError{
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "MmbModem: Error!: {}", self.error)
+		match *self {
+			MmbZigbeeModemError::Format(error) => write!(f, "MmbModem: Error!: {}", error),
+			MmbZigbeeModemError::InvalidChecksum{ expected, received } =>
+				write!(f, "MmbModem: Error!: Invalid checksum: expected 0x{:04X}, received 0x{:04X}", expected, received),
+			MmbZigbeeModemError::Io(ref error) => write!(f, "MmbModem: Error!: I/O: {}", error),
+			MmbZigbeeModemError::Timeout(waiting_for) => write!(f, "MmbModem: Error!: Timed out waiting for {}", waiting_for),
+			MmbZigbeeModemError::NotAcknowledged{ frame_seq_number } =>
+				write!(f, "MmbModem: Error!: Frame {} was never acknowledged", frame_seq_number),
+			MmbZigbeeModemError::CommandFailed{ waiting_for, frame_seq_number, status } =>
+				write!(f, "MmbModem: Error!: Frame {} failed while waiting for {}: {:?}", frame_seq_number, waiting_for, status),
+			MmbZigbeeModemError::CommandRejected{ waiting_for, frame_seq_number, error } =>
+				write!(f, "MmbModem: Error!: Frame {} rejected while waiting for {}: {:?}", frame_seq_number, waiting_for, error),
+			MmbZigbeeModemError::NotInitialized => write!(f, "MmbModem: Error!: The modem hasn't finished its startup"),
+			MmbZigbeeModemError::ZdoFailed{ cluster_id, status } =>
+				write!(f, "MmbModem: Error!: ZDO request 0x{:04X} failed with status 0x{:02X}", cluster_id, status),
+			MmbZigbeeModemError::ZclFailed{ cluster_id, command_id, status } =>
+				write!(f, "MmbModem: Error!: ZCL command 0x{:02X} on cluster 0x{:04X} failed with status 0x{:02X}",
+					command_id, cluster_id, status),
+			MmbZigbeeModemError::BootloadFailed(error) => write!(f, "MmbModem: Error!: Bootload failed: {}", error)
+		}
 	}
 }
 
+// Payload of the Status Response frames
+byte_enum! {
+    pub enum StatusCode {
+        SUCCESS = 0x00,
+        FAILURE = 0x01,
+        // The command isn't valid in the current state, like leaving when not on a network
+        INVALID_CALL = 0x02,
+        INVALID_PARAMETER = 0x03,
+        NOT_SUPPORTED = 0x04,
+        NOT_ON_NETWORK = 0x05,
+        TABLE_FULL = 0x06,
+    }
+}
+
+// Payload of the Error frames
+byte_enum! {
+    pub enum ErrorCode {
+        INVALID_CHECKSUM = 0x01,
+        INVALID_LENGTH = 0x02,
+        UNSUPPORTED_HEADER = 0x03,
+        MALFORMED_PAYLOAD = 0x04,
+    }
+}
+impl ErrorCode {
+    // The frame got corrupted on the UART, so sending it again may work
+    fn is_framing_error(&self) -> bool {
+        matches!(*self, ErrorCode::INVALID_CHECKSUM | ErrorCode::INVALID_LENGTH)
+    }
+}
+
 const START_OF_FRAME: u8 = 0xF1;
 const HEADER_SIZE: usize = 5;
 const CHECKSUM_SIZE: usize = 2;
+const PAYLOAD_LENGTH_OFFSET: usize = 4;
+const MAX_FRAME_SIZE: usize = HEADER_SIZE + 0xFF + CHECKSUM_SIZE;
+const STATUS_SUCCESS: u8 = 0x00;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+// Rebooting and going through the startup handshake again
+const RESTART_TIMEOUT: Duration = Duration::from_secs(30);
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum PrimaryHeader {
-    UTILITY_HEADER = 0x55,
-    NETWORK_COMMISSIONING_HEADER = 0x01,
-    SECURITY_CONFIG_HEADER  = 0x02,
-    ZIGBEE_SUPPORT_CONFIG_HEADER = 0x03,
-    ZDO_MESSAGES_HEADER = 0x04,
-    ZCL_MESSAGES_HEADER = 0x05,
-    GENERAL_CLUSTERS_HEADER = 0x11,
-    HA_CLUSTERS_HEADER = 0x12,
-    BOOTLOAD_HEADER = 0x0B,
-    OTA_BOOTLOAD_HEADER = 0xB0,
-    DIAGNOSTICS_HEADER = 0xD1,
-}
-impl From<u8> for PrimaryHeader {
-	fn from(num: u8) -> PrimaryHeader {
-		unsafe{ mem::transmute(num) }
-	}
-}
-
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum HeaderUtilities {
-    RESET = 0x00,
-    MODULE_INFO_REQUEST = 0x02,
-    MODULE_INFO_RESPONSE = 0x03,
-    BOOTLOADER_VERSION_REQUEST = 0x04,
-    BOOTLOADER_VERSION_RESPONSE = 0x05,
-    APPLICATION_VERSION_COUNT_REQUEST = 0x06,
-    APPLICATION_VERSION_COUNT_RESPONSE = 0x07,
-    APPLICATION_VERSION_REQUEST = 0x08,
-    APPLICATION_VERSION_RESPONSE = 0x09,
-    RESTORE_DEFAULTS = 0x10,
-    HOST_STARTUP_READY = 0x20,
-    STARTUP_SYNC_REQUEST = 0x21,
-    STARTUP_SYNC_COMPLETE = 0x22,
-    ANTENNA_CONFIGURATION_REQUEST = 0x23,
-    ANTENNA_CONFIGURATION_RESPONSE = 0x24,
-    ANTENNA_CONFIGURATION_WRITE = 0x25,
-    LED_CONFIGURATION_REQUEST = 0x26,
-    LED_CONFIGURATION_RESPONSE = 0x27,
-    LED_CONFIGURATION_WRITE = 0x28,
-    SERIAL_ACK_CONFIG_WRITE = 0x30,
-    SERIAL_ACK_CONFIG_REQUEST = 0x31,
-    SERIAL_ACK_CONFIG_RESPONSE = 0x32,
-    MANUFACTURER_ID_REQUEST = 0x40,
-    MANUFACTURER_ID_RESPONSE = 0x41,
-    MANUFACTURER_ID_WRITE = 0x42,
-    SLEEPY_PARAMETERS_REQUEST_CMD = 0x50,
-    SLEEPY_PARAMETERS_RESPONSE_CMD = 0x51,
-    SLEEPY_PARAMETERS_WRITE_CMD = 0x52,
-    SLEEPY_HIBERNATE_DURATION_REQUEST_CMD = 0x53,
-    SLEEPY_HIBERNATE_DURATION_RESPONSE_CMD = 0x54,
-    SLEEPY_HIBERNATE_DURATION_WRITE_CMD = 0x55,
-    STATUS_RESPONSE = 0x80,
-    ERROR = 0xE0,
-}
-impl From<u8> for HeaderUtilities {
-	fn from(num: u8) -> HeaderUtilities {
-		unsafe{ mem::transmute(num) }
-	}
-}
-
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum HeaderNetworkCommissioning {
-    JOIN_NETWORK = 0x00,
-    FORM_NETWORK = 0x01,
-    PERMIT_JOIN = 0x03,
-    LEAVE_NETWORK = 0x04,
-    REJOIN_NETWORK = 0x05,
-    NETWORK_STATUS_REQUEST = 0x08,
-    NETWORK_STATUS_RESPONSE = 0x09,
-    TRUST_CENTER_DEVICE_UPDATE = 0x10,
-    NETWORK_AUTO_JOIN = 0x11,
-    NETWORK_RESET_AUTO_JOIN = 0x12,
-}
-impl From<u8> for HeaderNetworkCommissioning {
-	fn from(num: u8) -> HeaderNetworkCommissioning {
-		unsafe{ mem::transmute(num) }
-	}
-}
-
-
-// TODO Type system is killing me... :(
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum HeaderNothing {
-    UNKNOWN = 0xFF
-}
-
-#[derive(Debug, PartialEq, Eq, Hash)]
+byte_enum! {
+    enum PrimaryHeader {
+        UTILITY_HEADER = 0x55,
+        NETWORK_COMMISSIONING_HEADER = 0x01,
+        SECURITY_CONFIG_HEADER  = 0x02,
+        ZIGBEE_SUPPORT_CONFIG_HEADER = 0x03,
+        ZDO_MESSAGES_HEADER = 0x04,
+        ZCL_MESSAGES_HEADER = 0x05,
+        GENERAL_CLUSTERS_HEADER = 0x11,
+        HA_CLUSTERS_HEADER = 0x12,
+        BOOTLOAD_HEADER = 0x0B,
+        OTA_BOOTLOAD_HEADER = 0xB0,
+        DIAGNOSTICS_HEADER = 0xD1,
+    }
+}
+
+byte_enum! {
+    enum HeaderUtilities {
+        RESET = 0x00,
+        MODULE_INFO_REQUEST = 0x02,
+        MODULE_INFO_RESPONSE = 0x03,
+        BOOTLOADER_VERSION_REQUEST = 0x04,
+        BOOTLOADER_VERSION_RESPONSE = 0x05,
+        APPLICATION_VERSION_COUNT_REQUEST = 0x06,
+        APPLICATION_VERSION_COUNT_RESPONSE = 0x07,
+        APPLICATION_VERSION_REQUEST = 0x08,
+        APPLICATION_VERSION_RESPONSE = 0x09,
+        RESTORE_DEFAULTS = 0x10,
+        HOST_STARTUP_READY = 0x20,
+        STARTUP_SYNC_REQUEST = 0x21,
+        STARTUP_SYNC_COMPLETE = 0x22,
+        ANTENNA_CONFIGURATION_REQUEST = 0x23,
+        ANTENNA_CONFIGURATION_RESPONSE = 0x24,
+        ANTENNA_CONFIGURATION_WRITE = 0x25,
+        LED_CONFIGURATION_REQUEST = 0x26,
+        LED_CONFIGURATION_RESPONSE = 0x27,
+        LED_CONFIGURATION_WRITE = 0x28,
+        SERIAL_ACK_CONFIG_WRITE = 0x30,
+        SERIAL_ACK_CONFIG_REQUEST = 0x31,
+        SERIAL_ACK_CONFIG_RESPONSE = 0x32,
+        MANUFACTURER_ID_REQUEST = 0x40,
+        MANUFACTURER_ID_RESPONSE = 0x41,
+        MANUFACTURER_ID_WRITE = 0x42,
+        SLEEPY_PARAMETERS_REQUEST_CMD = 0x50,
+        SLEEPY_PARAMETERS_RESPONSE_CMD = 0x51,
+        SLEEPY_PARAMETERS_WRITE_CMD = 0x52,
+        SLEEPY_HIBERNATE_DURATION_REQUEST_CMD = 0x53,
+        SLEEPY_HIBERNATE_DURATION_RESPONSE_CMD = 0x54,
+        SLEEPY_HIBERNATE_DURATION_WRITE_CMD = 0x55,
+        STATUS_RESPONSE = 0x80,
+        ERROR = 0xE0,
+    }
+}
+
+byte_enum! {
+    enum HeaderNetworkCommissioning {
+        JOIN_NETWORK = 0x00,
+        FORM_NETWORK = 0x01,
+        PERMIT_JOIN = 0x03,
+        LEAVE_NETWORK = 0x04,
+        REJOIN_NETWORK = 0x05,
+        NETWORK_STATUS_REQUEST = 0x08,
+        NETWORK_STATUS_RESPONSE = 0x09,
+        TRUST_CENTER_DEVICE_UPDATE = 0x10,
+        NETWORK_AUTO_JOIN = 0x11,
+        NETWORK_RESET_AUTO_JOIN = 0x12,
+    }
+}
+
+byte_enum! {
+    enum HeaderSecurityConfig {
+        SET_PRECONFIGURED_LINK_KEY = 0x00,
+        SET_NETWORK_KEY = 0x01,
+        NETWORK_KEY_REQUEST = 0x02,
+        NETWORK_KEY_RESPONSE = 0x03,
+        ADD_INSTALL_CODE = 0x04,
+        INSTALL_CODE_ONLY_JOINING_WRITE = 0x05,
+    }
+}
+
+byte_enum! {
+    enum HeaderZigbeeSupportConfig {
+        ADD_ENDPOINT = 0x00,
+        ENDPOINT_LIST_REQUEST = 0x01,
+        ENDPOINT_LIST_RESPONSE = 0x02,
+        SIMPLE_DESCRIPTOR_REQUEST = 0x03,
+        SIMPLE_DESCRIPTOR_RESPONSE = 0x04,
+    }
+}
+
+byte_enum! {
+    enum HeaderZdoMessages {
+        ZDO_UNICAST_REQUEST = 0x00,
+        ZDO_BROADCAST_REQUEST = 0x01,
+        ZDO_RESPONSE_RECEIVED = 0x02,
+    }
+}
+
+byte_enum! {
+    enum HeaderZclMessages {
+        SEND_ZCL_MESSAGE = 0x00,
+        ZCL_MESSAGE_RECEIVED = 0x01,
+    }
+}
+
+byte_enum! {
+    enum HeaderGeneralClusters {
+        RESET_TO_FACTORY_DEFAULTS = 0x00,
+        IDENTIFY = 0x01,
+        ON_OFF = 0x02,
+        MOVE_TO_LEVEL = 0x03,
+        MOVE_LEVEL = 0x04,
+        STEP_LEVEL = 0x05,
+        STOP_LEVEL = 0x06,
+        ADD_GROUP = 0x07,
+        REMOVE_GROUP = 0x08,
+        REMOVE_ALL_GROUPS = 0x09,
+        STORE_SCENE = 0x0A,
+        RECALL_SCENE = 0x0B,
+        REMOVE_SCENE = 0x0C,
+        REMOVE_ALL_SCENES = 0x0D,
+        CLUSTER_COMMAND_RECEIVED = 0x80,
+    }
+}
+
+byte_enum! {
+    enum HeaderHaClusters {
+        THERMOSTAT_SETPOINT_RAISE_LOWER = 0x00,
+        IAS_ZONE_ENROLL_RESPONSE = 0x01,
+        METER_READING_REQUEST = 0x02,
+        IAS_ZONE_ENROLL_REQUEST = 0x80,
+        IAS_ZONE_STATUS_CHANGE_NOTIFICATION = 0x81,
+        METER_READING_RESPONSE = 0x82,
+    }
+}
+
+byte_enum! {
+    enum HeaderBootload {
+        ENTER_BOOTLOAD_MODE = 0x00,
+    }
+}
+
+byte_enum! {
+    enum HeaderOtaBootload {
+        QUERY_NEXT_IMAGE_REQUEST = 0x00,
+        QUERY_NEXT_IMAGE_RESPONSE = 0x01,
+        IMAGE_BLOCK_REQUEST = 0x02,
+        IMAGE_BLOCK_RESPONSE = 0x03,
+        UPGRADE_END_REQUEST = 0x04,
+        UPGRADE_END_RESPONSE = 0x05,
+    }
+}
+
+byte_enum! {
+    enum HeaderDiagnostics {
+        COUNTERS_REQUEST = 0x00,
+        COUNTERS_RESPONSE = 0x01,
+        RESET_COUNTERS = 0x02,
+        LAST_HOP_INFO_REQUEST = 0x03,
+        LAST_HOP_INFO_RESPONSE = 0x04,
+        NEIGHBOR_TABLE_REQUEST = 0x05,
+        NEIGHBOR_TABLE_RESPONSE = 0x06,
+        CHILD_TABLE_REQUEST = 0x07,
+        CHILD_TABLE_RESPONSE = 0x08,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum SecondaryHeader{
     HeaderUtilities(HeaderUtilities),
     HeaderNetworkCommissioning(HeaderNetworkCommissioning),
-    HeaderNothing(HeaderNothing)
+    HeaderSecurityConfig(HeaderSecurityConfig),
+    HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig),
+    HeaderZdoMessages(HeaderZdoMessages),
+    HeaderZclMessages(HeaderZclMessages),
+    HeaderGeneralClusters(HeaderGeneralClusters),
+    HeaderHaClusters(HeaderHaClusters),
+    HeaderBootload(HeaderBootload),
+    HeaderOtaBootload(HeaderOtaBootload),
+    HeaderDiagnostics(HeaderDiagnostics),
+    // The secondary header of an unknown primary header
+    Unknown(u8)
 }
 
 impl SecondaryHeader {
 	fn from(primary_header: &PrimaryHeader, num: u8) -> SecondaryHeader {
-        match primary_header {
-            &PrimaryHeader::NETWORK_COMMISSIONING_HEADER => SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::from(num)),
-            &PrimaryHeader::UTILITY_HEADER => SecondaryHeader::HeaderUtilities(HeaderUtilities::from(num)),
-            _ => SecondaryHeader::HeaderNothing(HeaderNothing::UNKNOWN)
+        match *primary_header {
+            PrimaryHeader::NETWORK_COMMISSIONING_HEADER => SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::from(num)),
+            PrimaryHeader::UTILITY_HEADER => SecondaryHeader::HeaderUtilities(HeaderUtilities::from(num)),
+            PrimaryHeader::SECURITY_CONFIG_HEADER => SecondaryHeader::HeaderSecurityConfig(HeaderSecurityConfig::from(num)),
+            PrimaryHeader::ZIGBEE_SUPPORT_CONFIG_HEADER => SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::from(num)),
+            PrimaryHeader::ZDO_MESSAGES_HEADER => SecondaryHeader::HeaderZdoMessages(HeaderZdoMessages::from(num)),
+            PrimaryHeader::ZCL_MESSAGES_HEADER => SecondaryHeader::HeaderZclMessages(HeaderZclMessages::from(num)),
+            PrimaryHeader::GENERAL_CLUSTERS_HEADER => SecondaryHeader::HeaderGeneralClusters(HeaderGeneralClusters::from(num)),
+            PrimaryHeader::HA_CLUSTERS_HEADER => SecondaryHeader::HeaderHaClusters(HeaderHaClusters::from(num)),
+            PrimaryHeader::BOOTLOAD_HEADER => SecondaryHeader::HeaderBootload(HeaderBootload::from(num)),
+            PrimaryHeader::OTA_BOOTLOAD_HEADER => SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::from(num)),
+            PrimaryHeader::DIAGNOSTICS_HEADER => SecondaryHeader::HeaderDiagnostics(HeaderDiagnostics::from(num)),
+            PrimaryHeader::Unknown(_) => SecondaryHeader::Unknown(num)
+        }
+	}
+
+	fn to_u8(self) -> u8 {
+        match self {
+            SecondaryHeader::HeaderUtilities(header) => u8::from(header),
+            SecondaryHeader::HeaderNetworkCommissioning(header) => u8::from(header),
+            SecondaryHeader::HeaderSecurityConfig(header) => u8::from(header),
+            SecondaryHeader::HeaderZigbeeSupportConfig(header) => u8::from(header),
+            SecondaryHeader::HeaderZdoMessages(header) => u8::from(header),
+            SecondaryHeader::HeaderZclMessages(header) => u8::from(header),
+            SecondaryHeader::HeaderGeneralClusters(header) => u8::from(header),
+            SecondaryHeader::HeaderHaClusters(header) => u8::from(header),
+            SecondaryHeader::HeaderBootload(header) => u8::from(header),
+            SecondaryHeader::HeaderOtaBootload(header) => u8::from(header),
+            SecondaryHeader::HeaderDiagnostics(header) => u8::from(header),
+            SecondaryHeader::Unknown(num) => num
         }
 	}
 }
@@ -182,35 +377,115 @@ impl Header {
 			})
 		}
 	}
+
+	fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+		[self.start_of_frame,
+		 u8::from(self.primary_header),
+		 self.secondary_header.to_u8(),
+		 self.frame_seq_number,
+		 self.payload_length as u8]
+	}
+}
+
+// The MMB checksum is the 16 bit sum of every byte from the primary header to the end of
+// the payload (START_OF_FRAME is not included). It goes little endian at the end of the frame.
+fn checksum(buff: &[u8]) -> u16 {
+	buff.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
 }
 
 struct MmbZigbeeModemMessage{
 	header: Header,
 	payload: Vec<u8>,
-	checksum: [u8;2]
+	checksum: u16
 }
 impl MmbZigbeeModemMessage {
 	fn new(buff: &[u8]) -> Result<MmbZigbeeModemMessage, MmbZigbeeModemError>{
-		let mut offset = HEADER_SIZE;
-		let res = Header::new(&buff[0..offset]);
-		if let Err(error) = res {
-			return Err(error);
+		if buff.len() < HEADER_SIZE + CHECKSUM_SIZE {
+			return Err(MmbZigbeeModemError::new("Message format error: The message is too short"));
 		}
-		let header = res.unwrap();
-		if header.payload_length as usize != buff.len() - (HEADER_SIZE + CHECKSUM_SIZE) {
+		let payload_length = buff[PAYLOAD_LENGTH_OFFSET] as usize;
+		if payload_length != buff.len() - (HEADER_SIZE + CHECKSUM_SIZE) {
 			return Err(MmbZigbeeModemError::new("Message format error: The size of the message is different than expected"));
 		}
-		let payload = &buff[offset..offset + (header.payload_length - 1) as usize];
-		offset += (header.payload_length - 1) as usize;
-        // TODO: Check the checksum
-		let checksum = [buff[offset], buff[offset + 2]];
+		// Checked before decoding the header, so line noise doesn't get its headers decoded
+		let offset = HEADER_SIZE + payload_length;
+		let payload = &buff[HEADER_SIZE..offset];
+		let checksum = (buff[offset] as u16) | ((buff[offset + 1] as u16) << 8);
+		let expected = self::checksum(&buff[1..offset]);
+		if checksum != expected {
+			return Err(MmbZigbeeModemError::InvalidChecksum{ expected, received: checksum });
+		}
+		let header = Header::new(&buff[0..HEADER_SIZE])?;
+
+		Ok(MmbZigbeeModemMessage {
+			header,
+			payload: payload.to_vec(),
+			checksum
+		})
+	}
+
+	// Builds an outgoing frame, filling in the START_OF_FRAME, payload length and checksum.
+	fn build(primary_header: PrimaryHeader, secondary_header: SecondaryHeader, frame_seq_number: u8, payload: &[u8])
+		-> Result<MmbZigbeeModemMessage, MmbZigbeeModemError> {
+		if payload.len() > 0xFF {
+			return Err(MmbZigbeeModemError::new("Message format error: The payload doesn't fit in a frame"));
+		}
+		let header = Header {
+			start_of_frame: START_OF_FRAME,
+			primary_header,
+			secondary_header,
+			frame_seq_number,
+			payload_length: payload.len() as i32
+		};
+		let mut buff = header.to_bytes().to_vec();
+		buff.extend_from_slice(payload);
 
 		Ok(MmbZigbeeModemMessage {
-			header: header,
+			header,
 			payload: payload.to_vec(),
-			checksum: checksum
+			checksum: checksum(&buff[1..])
 		})
 	}
+
+	fn is_response(&self, secondary_header: SecondaryHeader, frame_seq_number: u8) -> bool {
+		self.header.secondary_header == secondary_header && self.header.frame_seq_number == frame_seq_number
+	}
+
+	fn is_status_response(&self, frame_seq_number: u8) -> bool {
+		self.is_response(SecondaryHeader::HeaderUtilities(HeaderUtilities::STATUS_RESPONSE), frame_seq_number)
+	}
+
+	fn is_error(&self, frame_seq_number: u8) -> bool {
+		self.is_response(SecondaryHeader::HeaderUtilities(HeaderUtilities::ERROR), frame_seq_number)
+	}
+
+	// Status Response frames answer the commands that have no explicit response
+	fn check_status(&self, waiting_for: &'static str) -> Result<(), MmbZigbeeModemError> {
+		match self.payload.first() {
+			Some(&status) => match StatusCode::from(status) {
+				StatusCode::SUCCESS => Ok(()),
+				status => Err(MmbZigbeeModemError::CommandFailed{
+					waiting_for,
+					frame_seq_number: self.header.frame_seq_number,
+					status
+				})
+			},
+			None => Err(MmbZigbeeModemError::new("Message format error: Status Response without status"))
+		}
+	}
+
+	fn error_code(&self) -> ErrorCode {
+		ErrorCode::from(self.payload.first().cloned().unwrap_or(0))
+	}
+
+	fn to_bytes(&self) -> Vec<u8> {
+		let mut buff = Vec::with_capacity(HEADER_SIZE + self.payload.len() + CHECKSUM_SIZE);
+		buff.extend_from_slice(&self.header.to_bytes());
+		buff.extend_from_slice(&self.payload);
+		buff.push(self.checksum as u8);
+		buff.push((self.checksum >> 8) as u8);
+		buff
+	}
 }
 impl fmt::Debug for MmbZigbeeModemMessage {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -218,41 +493,348 @@ impl fmt::Debug for MmbZigbeeModemMessage {
 	}
 }
 
-struct MessageHandler;
-impl MessageHandler{
-    fn startup(mut serial_port: Rc<RefCell<ZigbeeSerialPort>>, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
-        let mut _serial_port = serial_port.borrow_mut();
-        match _serial_port.write("12345".as_bytes()){
-            Ok(bytes_written) => {
-                trace!("{} bytes writen!!", bytes_written);
-                Ok(())
-            },
-            Err(e) => {
-                trace!("Error!!: e = {:?}", e);
-                Err(e.to_string())
-            }
+// Payloads are made of little endian fields. Reading past the end of the payload is a format
+// error, not an I/O one.
+struct PayloadReader<'a> {
+	cursor: Cursor<&'a [u8]>
+}
+impl<'a> PayloadReader<'a> {
+	fn new(payload: &'a [u8]) -> PayloadReader<'a> {
+		PayloadReader {
+			cursor: Cursor::new(payload)
+		}
+	}
+
+	fn truncated(_: Error) -> MmbZigbeeModemError {
+		MmbZigbeeModemError::new("Message format error: The payload is shorter than expected")
+	}
+
+	fn u8(&mut self) -> Result<u8, MmbZigbeeModemError> {
+		self.cursor.read_u8().map_err(Self::truncated)
+	}
+
+	fn i8(&mut self) -> Result<i8, MmbZigbeeModemError> {
+		self.cursor.read_i8().map_err(Self::truncated)
+	}
+
+	fn u16(&mut self) -> Result<u16, MmbZigbeeModemError> {
+		self.cursor.read_u16::<LittleEndian>().map_err(Self::truncated)
+	}
+
+	fn u32(&mut self) -> Result<u32, MmbZigbeeModemError> {
+		self.cursor.read_u32::<LittleEndian>().map_err(Self::truncated)
+	}
+
+	fn u64(&mut self) -> Result<u64, MmbZigbeeModemError> {
+		self.cursor.read_u64::<LittleEndian>().map_err(Self::truncated)
+	}
+
+	// For the odd sized integers ZCL uses, like 24 and 48 bits
+	fn uint(&mut self, nbytes: usize) -> Result<u64, MmbZigbeeModemError> {
+		self.cursor.read_uint::<LittleEndian>(nbytes).map_err(Self::truncated)
+	}
 
+	fn int(&mut self, nbytes: usize) -> Result<i64, MmbZigbeeModemError> {
+		self.cursor.read_int::<LittleEndian>(nbytes).map_err(Self::truncated)
+	}
+
+	fn bytes(&mut self, len: usize) -> Result<Vec<u8>, MmbZigbeeModemError> {
+		let mut buff = vec![0; len];
+		self.cursor.read_exact(&mut buff).map_err(Self::truncated)?;
+		Ok(buff)
+	}
+
+	fn remaining(&self) -> &'a [u8] {
+		let payload = *self.cursor.get_ref();
+		&payload[(self.cursor.position() as usize).min(payload.len())..]
+	}
+}
+
+// Writing to a Vec can't fail, so the byteorder results are safe to ignore
+struct PayloadWriter {
+	buff: Vec<u8>
+}
+impl PayloadWriter {
+	fn new() -> PayloadWriter {
+		PayloadWriter {
+			buff: Vec::new()
+		}
+	}
+
+	fn u8(&mut self, value: u8) -> &mut PayloadWriter {
+		self.buff.push(value);
+		self
+	}
+
+	fn i8(&mut self, value: i8) -> &mut PayloadWriter {
+		self.buff.push(value as u8);
+		self
+	}
+
+	fn u16(&mut self, value: u16) -> &mut PayloadWriter {
+		let _ = self.buff.write_u16::<LittleEndian>(value);
+		self
+	}
+
+	fn u32(&mut self, value: u32) -> &mut PayloadWriter {
+		let _ = self.buff.write_u32::<LittleEndian>(value);
+		self
+	}
+
+	fn u64(&mut self, value: u64) -> &mut PayloadWriter {
+		let _ = self.buff.write_u64::<LittleEndian>(value);
+		self
+	}
+
+	fn uint(&mut self, value: u64, nbytes: usize) -> &mut PayloadWriter {
+		let _ = self.buff.write_uint::<LittleEndian>(value, nbytes);
+		self
+	}
+
+	fn bytes(&mut self, buff: &[u8]) -> &mut PayloadWriter {
+		self.buff.extend_from_slice(buff);
+		self
+	}
+
+	fn into_vec(self) -> Vec<u8> {
+		self.buff
+	}
+}
+
+// A single read from the UART can return half a frame, several frames or some line noise
+// before the START_OF_FRAME byte, so incoming bytes are buffered here and split into
+// frames using the payload length of the header.
+struct MmbFrameReassembler {
+    buffer: Vec<u8>
+}
+impl MmbFrameReassembler {
+    fn new() -> MmbFrameReassembler {
+        MmbFrameReassembler {
+            buffer: Vec::with_capacity(MAX_FRAME_SIZE)
         }
     }
 
-    fn form_network(msg: &MmbZigbeeModemMessage) -> Result<(),String>{
-        Ok(())
+    fn push(&mut self, buff: &[u8]) {
+        self.buffer.extend_from_slice(buff);
     }
 
-    fn join_network(msg: &MmbZigbeeModemMessage) -> Result<(),String>{
-        Ok(())
+    // Returns the next complete frame, or None if we need more bytes. Everything before a
+    // START_OF_FRAME is dropped. If a frame can't be parsed only its START_OF_FRAME byte is
+    // dropped, so we can resynchronise on the next one.
+    fn next_frame(&mut self) -> Option<Result<MmbZigbeeModemMessage, MmbZigbeeModemError>> {
+        loop {
+            match self.buffer.iter().position(|&byte| byte == START_OF_FRAME) {
+                Some(0) => {},
+                Some(garbage) => {
+                    trace!("Dropping {} bytes before START_OF_FRAME", garbage);
+                    self.buffer.drain(..garbage);
+                },
+                None => {
+                    if !self.buffer.is_empty() {
+                        trace!("Dropping {} bytes without START_OF_FRAME", self.buffer.len());
+                        self.buffer.clear();
+                    }
+                    return None;
+                }
+            }
+
+            if self.buffer.len() < HEADER_SIZE {
+                return None;
+            }
+            let frame_size = HEADER_SIZE + self.buffer[PAYLOAD_LENGTH_OFFSET] as usize + CHECKSUM_SIZE;
+            if self.buffer.len() < frame_size {
+                // Don't let a bogus payload length hold back the frames behind it
+                if self.valid_frame_follows() {
+                    trace!("Dropping a START_OF_FRAME whose frame never completed");
+                    self.buffer.remove(0);
+                    continue;
+                }
+                return None;
+            }
+
+            return match MmbZigbeeModemMessage::new(&self.buffer[..frame_size]) {
+                Ok(msg) => {
+                    self.buffer.drain(..frame_size);
+                    Some(Ok(msg))
+                },
+                Err(error) => {
+                    self.buffer.remove(0);
+                    Some(Err(error))
+                }
+            };
+        }
     }
 
-    fn not_implemented() -> Result<(),String>{
-        Err("Message handler not implemented!".to_string())
+    // Whether a complete frame with a good checksum starts after the first START_OF_FRAME
+    fn valid_frame_follows(&self) -> bool {
+        (1..self.buffer.len())
+            .filter(|&start| self.buffer[start] == START_OF_FRAME)
+            .any(|start| {
+                let candidate = &self.buffer[start..];
+                if candidate.len() < HEADER_SIZE {
+                    return false;
+                }
+                let frame_size = HEADER_SIZE + candidate[PAYLOAD_LENGTH_OFFSET] as usize + CHECKSUM_SIZE;
+                candidate.len() >= frame_size && MmbZigbeeModemMessage::new(&candidate[..frame_size]).is_ok()
+            })
     }
 }
 
 
+// UNINITIALIZED -> (Startup Sync Request answered) -> INITIALIZING -> (Startup Sync Complete
+// received and Host Startup Ready acknowledged) -> INITIALIZED
+// Once initialized, the Network Status frames move the modem between INITIALIZED (no
+// network), JOINING_NETWORK and ON_NETWORK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
 pub enum MmbZigbeeModemState {
     UNINITIALIZED,
     INITIALIZING,
-    INITIALIZED
+    INITIALIZED,
+    JOINING_NETWORK,
+    ON_NETWORK
+}
+impl MmbZigbeeModemState {
+    pub fn is_initialized(&self) -> bool {
+        !matches!(*self, MmbZigbeeModemState::UNINITIALIZED | MmbZigbeeModemState::INITIALIZING)
+    }
+}
+
+#[derive(Debug)]
+pub enum MmbZigbeeModemEvent {
+    // Serial ACK mode gave up retransmitting a frame
+    DeliveryFailed{ frame_seq_number: u8 },
+    StateChanged(MmbZigbeeModemState),
+    StartupFailed(MmbZigbeeModemError),
+    NetworkStatusChanged(NetworkStatus),
+    TrustCenterDeviceUpdate(DeviceUpdate),
+    AttributeReported(AttributeKey, AttributeValue),
+    ClusterCommandReceived(ZclSource, GeneralClusterCommand),
+    // An IAS zone wants to be enrolled, answer with zone_enroll_response()
+    ZoneEnrollRequested(ZoneEnrollRequest),
+    ZoneStatusChanged(ZoneStatusChange),
+    MeterReadingReceived(MeterReading),
+    OtaUpgradeEnded(OtaUpgradeEnd),
+    UnhandledFrame(OpaqueFrame)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpaqueFrame {
+    pub primary_header: u8,
+    pub secondary_header: u8,
+    pub frame_seq_number: u8,
+    pub payload: Vec<u8>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u16
+}
+impl FirmwareVersion {
+    fn read(reader: &mut PayloadReader) -> Result<FirmwareVersion, MmbZigbeeModemError> {
+        Ok(FirmwareVersion {
+            major: reader.u8()?,
+            minor: reader.u8()?,
+            build: reader.u16()?
+        })
+    }
+}
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleInfo {
+    pub eui64: u64,
+    pub hardware_revision: u8,
+    pub manufacturer_id: u16,
+    pub bootloader_version: FirmwareVersion,
+    pub application_versions: Vec<FirmwareVersion>
+}
+
+byte_enum! {
+    pub enum AntennaMode {
+        // Chip antenna on the module
+        INTERNAL = 0x00,
+        // U.FL connector
+        EXTERNAL = 0x01,
+    }
+}
+
+// [Antenna mode: 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AntennaConfiguration {
+    pub mode: AntennaMode
+}
+
+byte_enum! {
+    pub enum LedMode {
+        OFF = 0x00,
+        // Blinks while joining, steady when on a network
+        NETWORK_STATUS = 0x01,
+        // Blinks on radio traffic
+        ACTIVITY = 0x02,
+    }
+}
+
+// [LED mode: 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedConfiguration {
+    pub mode: LedMode
+}
+
+// Only used when the module joins as a sleepy end device
+// [Poll rate: 2][Wake timeout: 2]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepyParameters {
+    // How often the module polls its parent while awake, in quarter seconds
+    pub poll_rate: u16,
+    // Seconds the module stays awake after the last host or network activity
+    pub wake_timeout: u16
+}
+
+// How long each step of the startup handshake may take once the module has sent its
+// Startup Sync Request.
+#[derive(Debug, Clone, Copy)]
+pub struct StartupTimeouts {
+    pub sync_complete: Duration,
+    pub host_startup_ready: Duration
+}
+impl Default for StartupTimeouts {
+    fn default() -> StartupTimeouts {
+        StartupTimeouts {
+            sync_complete: Duration::from_secs(10),
+            host_startup_ready: RESPONSE_TIMEOUT
+        }
+    }
+}
+
+// When serial ACKs are enabled both ends answer every frame they receive with a Status Response
+// carrying the same sequence number, before any response to the frame itself. Frames that
+// aren't acknowledged within `timeout` (or that the module rejects with an Error frame) are
+// sent again, up to `retries` times.
+#[derive(Debug, Clone, Copy)]
+pub struct SerialAckConfig {
+    pub timeout: Duration,
+    pub retries: u8
+}
+impl Default for SerialAckConfig {
+    fn default() -> SerialAckConfig {
+        SerialAckConfig {
+            timeout: Duration::from_millis(500),
+            retries: 3
+        }
+    }
+}
+
+struct UnackedFrame {
+    frame: Vec<u8>,
+    deadline: Instant,
+    retries_left: u8
 }
 
 
@@ -262,53 +844,682 @@ pub enum MmbZigbeeModemState {
 
 pub struct MmbZigbeeModemProtocol {
     state: MmbZigbeeModemState,
-    serial_port: Option<Rc<RefCell<ZigbeeSerialPort>>>
+    serial_port: Option<Rc<RefCell<ZigbeeSerialPort>>>,
+    reassembler: MmbFrameReassembler,
+    frame_seq_number: u8,
+    serial_ack: Option<SerialAckConfig>,
+    unacked_frames: HashMap<u8, UnackedFrame>,
+    // Sequence number of the last frame we acknowledged, to spot the ones the module sends
+    // again when our ACK gets lost
+    last_received_seq_number: Option<u8>,
+    events: VecDeque<MmbZigbeeModemEvent>,
+    startup_timeouts: StartupTimeouts,
+    // Deadline of the current startup step, and the sequence number of our Host Startup Ready
+    // once we have sent it.
+    startup_deadline: Option<Instant>,
+    host_startup_ready_seq_number: Option<u8>,
+    network_status: Option<NetworkStatus>,
+    // Network Status frames received while a commissioning command waits for its Status Response
+    network_status_log: Option<Vec<NetworkStatus>>,
+    auto_join: bool,
+    zdo_seq_number: u8,
+    zcl_seq_number: u8,
+    zcl_source_endpoint: u8,
+    zcl_profile_id: u16,
+    attribute_values: HashMap<AttributeKey, AttributeValue>,
+    ota_images: Vec<OtaImage>
 }
+impl Default for MmbZigbeeModemProtocol {
+    fn default() -> MmbZigbeeModemProtocol {
+        MmbZigbeeModemProtocol::new()
+    }
+}
+
 impl MmbZigbeeModemProtocol {
     pub fn new()-> MmbZigbeeModemProtocol {
         MmbZigbeeModemProtocol {
             state: MmbZigbeeModemState::UNINITIALIZED,
-            serial_port: None
+            serial_port: None,
+            reassembler: MmbFrameReassembler::new(),
+            frame_seq_number: 0,
+            serial_ack: None,
+            unacked_frames: HashMap::new(),
+            last_received_seq_number: None,
+            events: VecDeque::new(),
+            startup_timeouts: StartupTimeouts::default(),
+            startup_deadline: None,
+            host_startup_ready_seq_number: None,
+            network_status: None,
+            network_status_log: None,
+            auto_join: false,
+            zdo_seq_number: 0,
+            zcl_seq_number: 0,
+            zcl_source_endpoint: 1,
+            zcl_profile_id: HOME_AUTOMATION_PROFILE_ID,
+            attribute_values: HashMap::new(),
+            ota_images: Vec::new()
         }
     }
 
-    fn process(&self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+    pub fn poll_event(&mut self) -> Option<MmbZigbeeModemEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn state(&self) -> MmbZigbeeModemState {
+        self.state
+    }
+
+    pub fn set_startup_timeouts(&mut self, timeouts: StartupTimeouts) {
+        self.startup_timeouts = timeouts;
+    }
+
+    // Blocks until the startup handshake with the module has finished
+    pub fn wait_for_startup(&mut self, timeout: Duration) -> Result<(), MmbZigbeeModemError> {
+        self.wait_until(timeout, "modem startup", |protocol| protocol.state.is_initialized())
+    }
+
+    // Reboots the module, and returns once the startup handshake has finished again
+    pub fn reset(&mut self) -> Result<(), MmbZigbeeModemError> {
+        self.restart(HeaderUtilities::RESET, "Reset status")
+    }
+
+    // Like reset(), but the module also forgets its network and configuration
+    pub fn factory_reset(&mut self) -> Result<(), MmbZigbeeModemError> {
+        self.restart(HeaderUtilities::RESTORE_DEFAULTS, "Restore Defaults status")?;
+        self.auto_join = false;
+        Ok(())
+    }
+
+    fn restart(&mut self, command: HeaderUtilities, waiting_for: &'static str) -> Result<(), MmbZigbeeModemError> {
+        let frame_seq_number = self.send_command(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(command), &[])?;
+        // With serial ACKs on, a rebooting module may never acknowledge the frame, and
+        // retransmitting it would restart the module again once it's up. If the ACK does come
+        // it stands in for the Status Response.
+        self.unacked_frames.remove(&frame_seq_number);
+        // The module may reboot before its Status Response makes it through the UART
+        let startup_sync_request = SecondaryHeader::HeaderUtilities(HeaderUtilities::STARTUP_SYNC_REQUEST);
+        let msg = self.wait_for_frame(Some(frame_seq_number), RESPONSE_TIMEOUT, waiting_for,
+            |msg| msg.is_status_response(frame_seq_number) || msg.header.secondary_header == startup_sync_request)?;
+        if msg.header.secondary_header != startup_sync_request {
+            msg.check_status(waiting_for)?;
+        }
+
+        self.set_state(MmbZigbeeModemState::UNINITIALIZED);
+        self.unacked_frames.clear();
+        self.startup_deadline = None;
+        self.host_startup_ready_seq_number = None;
+        if command == HeaderUtilities::RESTORE_DEFAULTS {
+            // Serial ACKs are disabled by default
+            self.serial_ack = None;
+        }
+        if msg.header.secondary_header == startup_sync_request {
+            if let Err(msg) = self.process(&msg) {
+                error!("Error parsing message from the UART: {}", msg);
+            }
+        }
+        self.wait_for_startup(RESTART_TIMEOUT)
+    }
+
+    fn set_state(&mut self, state: MmbZigbeeModemState) {
+        if self.state != state {
+            trace!("Modem state: {:?} -> {:?}", self.state, state);
+            self.state = state;
+            self.events.push_back(MmbZigbeeModemEvent::StateChanged(state));
+        }
+    }
+
+    fn on_startup_sync_request(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        trace!("Startup Sync Request: {:?}", msg.payload);
+        // The module (re)started, so nothing we sent before is going to be acknowledged, and it
+        // may reuse the sequence numbers of the frames we got from it
+        self.unacked_frames.clear();
+        self.last_received_seq_number = None;
+        self.network_status = None;
+        self.host_startup_ready_seq_number = None;
+        self.startup_deadline = Some(Instant::now() + self.startup_timeouts.sync_complete);
+        self.set_state(MmbZigbeeModemState::INITIALIZING);
+        self.send_status_response(msg.header.frame_seq_number, STATUS_SUCCESS)
+            .map_err(|e| format!("Couldn't answer the Startup Sync Request: {:?}", e))
+    }
+
+    fn on_startup_sync_complete(&mut self) -> Result<(), String> {
+        if self.state != MmbZigbeeModemState::INITIALIZING || self.host_startup_ready_seq_number.is_some() {
+            return Err("Unexpected Startup Sync Complete".to_string());
+        }
+        match self.send(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(HeaderUtilities::HOST_STARTUP_READY), &[]) {
+            Ok(frame_seq_number) => {
+                self.host_startup_ready_seq_number = Some(frame_seq_number);
+                self.startup_deadline = Some(Instant::now() + self.startup_timeouts.host_startup_ready);
+                Ok(())
+            },
+            Err(e) => {
+                self.abort_startup(e);
+                Err("Couldn't send Host Startup Ready".to_string())
+            }
+        }
+    }
+
+    fn on_status_response(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        if self.host_startup_ready_seq_number != Some(msg.header.frame_seq_number) {
+            // Nobody is waiting for it, like the answers to the frames we send while processing
+            if let Err(e) = msg.check_status("Status Response") {
+                warn!("{:?}", e);
+            }
+            return Ok(());
+        }
+        match msg.check_status("Host Startup Ready status") {
+            Ok(()) => {
+                self.host_startup_ready_seq_number = None;
+                self.startup_deadline = None;
+                self.set_state(MmbZigbeeModemState::INITIALIZED);
+                Ok(())
+            },
+            Err(e) => {
+                self.abort_startup(e);
+                Err("Host Startup Ready rejected by the module".to_string())
+            }
+        }
+    }
+
+    fn on_error(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let frame_seq_number = msg.header.frame_seq_number;
+        if self.is_retransmitting(msg) {
+            return Ok(());
+        }
+        if self.host_startup_ready_seq_number == Some(frame_seq_number) {
+            self.abort_startup(MmbZigbeeModemError::CommandRejected{
+                waiting_for: "Host Startup Ready status",
+                frame_seq_number,
+                error: msg.error_code()
+            });
+            return Err("Host Startup Ready rejected by the module".to_string());
+        }
+        Err(format!("Frame {} rejected by the module: {:?}", frame_seq_number, msg.error_code()))
+    }
+
+    fn check_startup_timeout(&mut self) {
+        match self.startup_deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                let waiting_for = match self.host_startup_ready_seq_number {
+                    Some(_) => "Host Startup Ready status",
+                    None => "Startup Sync Complete"
+                };
+                self.abort_startup(MmbZigbeeModemError::Timeout(waiting_for));
+            },
+            _ => {}
+        }
+    }
+
+    // Back to square one: the module will send a new Startup Sync Request
+    fn abort_startup(&mut self, error: MmbZigbeeModemError) {
+        error!("Startup handshake failed: {:?}", error);
+        self.startup_deadline = None;
+        self.host_startup_ready_seq_number = None;
+        self.set_state(MmbZigbeeModemState::UNINITIALIZED);
+        self.events.push_back(MmbZigbeeModemEvent::StartupFailed(error));
+    }
+
+    fn run_timers(&mut self) -> Vec<u8> {
+        self.check_startup_timeout();
+        self.retransmit_unacked_frames()
+    }
+
+    pub fn enable_serial_ack(&mut self, config: SerialAckConfig) -> Result<(), MmbZigbeeModemError> {
+        self.ensure_initialized()?;
+        // The module only starts acknowledging frames once it has accepted this one
+        self.write_serial_ack_config(true)?;
+        self.serial_ack = Some(config);
+        self.last_received_seq_number = None;
+        Ok(())
+    }
+
+    pub fn disable_serial_ack(&mut self) -> Result<(), MmbZigbeeModemError> {
+        // Not tracked, so the first Status Response we get for it is taken as its result
+        self.serial_ack = None;
+        self.unacked_frames.clear();
+        self.write_serial_ack_config(false)
+    }
+
+    // Asks the module whether serial ACKs are currently enabled
+    pub fn serial_ack_enabled(&mut self) -> Result<bool, MmbZigbeeModemError> {
+        let payload = self.utility_request(HeaderUtilities::SERIAL_ACK_CONFIG_REQUEST,
+            HeaderUtilities::SERIAL_ACK_CONFIG_RESPONSE, &[], "Serial ACK Config Response")?;
+        Ok(PayloadReader::new(&payload).u8()? != 0)
+    }
+
+    fn write_serial_ack_config(&mut self, enabled: bool) -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(HeaderUtilities::SERIAL_ACK_CONFIG_WRITE),
+            &[enabled as u8], "Serial ACK Config Write status")
+    }
+
+    // Reads the EUI64, hardware revision, manufacturer ID and the bootloader and application
+    // versions of the module.
+    pub fn module_info(&mut self) -> Result<ModuleInfo, MmbZigbeeModemError> {
+        // [EUI64: 8][Hardware revision: 1]
+        let payload = self.utility_request(HeaderUtilities::MODULE_INFO_REQUEST,
+            HeaderUtilities::MODULE_INFO_RESPONSE, &[], "Module Info Response")?;
+        let mut reader = PayloadReader::new(&payload);
+        let eui64 = reader.u64()?;
+        let hardware_revision = reader.u8()?;
+
+        // [Major: 1][Minor: 1][Build: 2]
+        let payload = self.utility_request(HeaderUtilities::BOOTLOADER_VERSION_REQUEST,
+            HeaderUtilities::BOOTLOADER_VERSION_RESPONSE, &[], "Bootloader Version Response")?;
+        let bootloader_version = FirmwareVersion::read(&mut PayloadReader::new(&payload))?;
+
+        let payload = self.utility_request(HeaderUtilities::APPLICATION_VERSION_COUNT_REQUEST,
+            HeaderUtilities::APPLICATION_VERSION_COUNT_RESPONSE, &[], "Application Version Count Response")?;
+        let application_count = PayloadReader::new(&payload).u8()?;
+
+        let mut application_versions = Vec::with_capacity(application_count as usize);
+        for index in 0..application_count {
+            application_versions.push(self.application_version(index)?);
+        }
+
+        let payload = self.utility_request(HeaderUtilities::MANUFACTURER_ID_REQUEST,
+            HeaderUtilities::MANUFACTURER_ID_RESPONSE, &[], "Manufacturer ID Response")?;
+        let manufacturer_id = PayloadReader::new(&payload).u16()?;
+
+        Ok(ModuleInfo {
+            eui64,
+            hardware_revision,
+            manufacturer_id,
+            bootloader_version,
+            application_versions
+        })
+    }
+
+    pub fn antenna_configuration(&mut self) -> Result<AntennaConfiguration, MmbZigbeeModemError> {
+        let payload = self.utility_request(HeaderUtilities::ANTENNA_CONFIGURATION_REQUEST,
+            HeaderUtilities::ANTENNA_CONFIGURATION_RESPONSE, &[], "Antenna Configuration Response")?;
+        Ok(AntennaConfiguration {
+            mode: AntennaMode::from(PayloadReader::new(&payload).u8()?)
+        })
+    }
+
+    pub fn set_antenna_configuration(&mut self, configuration: AntennaConfiguration) -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(HeaderUtilities::ANTENNA_CONFIGURATION_WRITE),
+            &[u8::from(configuration.mode)], "Antenna Configuration Write status")
+    }
+
+    pub fn led_configuration(&mut self) -> Result<LedConfiguration, MmbZigbeeModemError> {
+        let payload = self.utility_request(HeaderUtilities::LED_CONFIGURATION_REQUEST,
+            HeaderUtilities::LED_CONFIGURATION_RESPONSE, &[], "LED Configuration Response")?;
+        Ok(LedConfiguration {
+            mode: LedMode::from(PayloadReader::new(&payload).u8()?)
+        })
+    }
+
+    pub fn set_led_configuration(&mut self, configuration: LedConfiguration) -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(HeaderUtilities::LED_CONFIGURATION_WRITE),
+            &[u8::from(configuration.mode)], "LED Configuration Write status")
+    }
+
+    pub fn sleepy_parameters(&mut self) -> Result<SleepyParameters, MmbZigbeeModemError> {
+        let payload = self.utility_request(HeaderUtilities::SLEEPY_PARAMETERS_REQUEST_CMD,
+            HeaderUtilities::SLEEPY_PARAMETERS_RESPONSE_CMD, &[], "Sleepy Parameters Response")?;
+        let mut reader = PayloadReader::new(&payload);
+        Ok(SleepyParameters {
+            poll_rate: reader.u16()?,
+            wake_timeout: reader.u16()?
+        })
+    }
+
+    pub fn set_sleepy_parameters(&mut self, parameters: SleepyParameters) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(parameters.poll_rate).u16(parameters.wake_timeout);
+        self.execute(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(HeaderUtilities::SLEEPY_PARAMETERS_WRITE_CMD),
+            &payload.into_vec(), "Sleepy Parameters Write status")
+    }
+
+    // How long a sleepy module hibernates, without polling its parent, when the host asks it to.
+    // [Duration: 4, seconds]
+    pub fn sleepy_hibernate_duration(&mut self) -> Result<Duration, MmbZigbeeModemError> {
+        let payload = self.utility_request(HeaderUtilities::SLEEPY_HIBERNATE_DURATION_REQUEST_CMD,
+            HeaderUtilities::SLEEPY_HIBERNATE_DURATION_RESPONSE_CMD, &[], "Sleepy Hibernate Duration Response")?;
+        Ok(Duration::from_secs(PayloadReader::new(&payload).u32()? as u64))
+    }
+
+    pub fn set_sleepy_hibernate_duration(&mut self, duration: Duration) -> Result<(), MmbZigbeeModemError> {
+        if duration.as_secs() > u32::MAX as u64 {
+            return Err(MmbZigbeeModemError::new("Sleepy Hibernate Duration: Duration too long"));
+        }
+        let mut payload = PayloadWriter::new();
+        payload.u32(duration.as_secs() as u32);
+        self.execute(PrimaryHeader::UTILITY_HEADER,
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::SLEEPY_HIBERNATE_DURATION_WRITE_CMD),
+            &payload.into_vec(), "Sleepy Hibernate Duration Write status")
+    }
+
+    fn application_version(&mut self, index: u8) -> Result<FirmwareVersion, MmbZigbeeModemError> {
+        // [Index: 1][Major: 1][Minor: 1][Build: 2]
+        let payload = self.utility_request(HeaderUtilities::APPLICATION_VERSION_REQUEST,
+            HeaderUtilities::APPLICATION_VERSION_RESPONSE, &[index], "Application Version Response")?;
+        let mut reader = PayloadReader::new(&payload);
+        if reader.u8()? != index {
+            return Err(MmbZigbeeModemError::new("Message format error: Application Version Response for another index"));
+        }
+        FirmwareVersion::read(&mut reader)
+    }
+
+    // Sends a command and waits for its explicit response, which carries the same sequence number
+    fn transact(&mut self, primary_header: PrimaryHeader, request: SecondaryHeader, response: SecondaryHeader,
+        payload: &[u8], waiting_for: &'static str) -> Result<MmbZigbeeModemMessage, MmbZigbeeModemError> {
+        let frame_seq_number = self.send_command(primary_header, request, payload)?;
+        self.wait_for_frame(Some(frame_seq_number), RESPONSE_TIMEOUT, waiting_for,
+            |msg| msg.is_response(response, frame_seq_number))
+    }
+
+    // Sends a command that is answered with a Status Response, and checks the status. With
+    // serial ACKs enabled, handle_serial_ack() swallows the ACK that comes before it.
+    fn execute(&mut self, primary_header: PrimaryHeader, command: SecondaryHeader, payload: &[u8],
+        waiting_for: &'static str) -> Result<(), MmbZigbeeModemError> {
+        let frame_seq_number = self.send_command(primary_header, command, payload)?;
+        self.wait_for_frame(Some(frame_seq_number), RESPONSE_TIMEOUT, waiting_for,
+            |msg| msg.is_status_response(frame_seq_number))?.check_status(waiting_for)
+    }
+
+    fn utility_request(&mut self, request: HeaderUtilities, response: HeaderUtilities, payload: &[u8],
+        waiting_for: &'static str) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let msg = self.transact(PrimaryHeader::UTILITY_HEADER, SecondaryHeader::HeaderUtilities(request),
+            SecondaryHeader::HeaderUtilities(response), payload, waiting_for)?;
+        Ok(msg.payload)
+    }
+
+    // Sequence numbers are per connection and wrap around after 0xFF
+    fn next_frame_seq_number(&mut self) -> u8 {
+        let frame_seq_number = self.frame_seq_number;
+        self.frame_seq_number = self.frame_seq_number.wrapping_add(1);
+        frame_seq_number
+    }
+
+    // Encodes and sends a frame to the modem. Returns the sequence number assigned to it.
+    fn send(&mut self, primary_header: PrimaryHeader, secondary_header: SecondaryHeader, payload: &[u8])
+        -> Result<u8, MmbZigbeeModemError> {
+        let frame_seq_number = self.next_frame_seq_number();
+        let msg = MmbZigbeeModemMessage::build(primary_header, secondary_header, frame_seq_number, payload)?;
+        let frame = msg.to_bytes();
+        self.write(&frame).map_err(MmbZigbeeModemError::Io)?;
+        if let Some(config) = self.serial_ack {
+            self.unacked_frames.insert(frame_seq_number, UnackedFrame {
+                frame,
+                deadline: Instant::now() + config.timeout,
+                retries_left: config.retries
+            });
+        }
+        Ok(frame_seq_number)
+    }
+
+    // Same as send(), for the commands that need the startup handshake to be finished
+    fn send_command(&mut self, primary_header: PrimaryHeader, secondary_header: SecondaryHeader, payload: &[u8])
+        -> Result<u8, MmbZigbeeModemError> {
+        self.ensure_initialized()?;
+        self.send(primary_header, secondary_header, payload)
+    }
+
+    fn ensure_initialized(&self) -> Result<(), MmbZigbeeModemError> {
+        if self.state.is_initialized() {
+            Ok(())
+        } else {
+            Err(MmbZigbeeModemError::NotInitialized)
+        }
+    }
+
+    // Status Responses reuse the sequence number of the frame they answer, and are never
+    // acknowledged themselves.
+    fn send_status_response(&mut self, frame_seq_number: u8, status: u8) -> Result<(), MmbZigbeeModemError> {
+        let msg = MmbZigbeeModemMessage::build(PrimaryHeader::UTILITY_HEADER,
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::STATUS_RESPONSE), frame_seq_number, &[status])?;
+        self.write(&msg.to_bytes()).map_err(MmbZigbeeModemError::Io)
+    }
+
+    // Serial ACK bookkeeping for every frame coming from the module. The first Status Response
+    // of a frame we sent acknowledges it; an Error frame rejects it, so it is retransmitted
+    // straight away. The rest of the frames are acknowledged back. Returns false for the frames
+    // that stop here: the ACKs themselves and the frames the module sent twice.
+    fn handle_serial_ack(&mut self, msg: &MmbZigbeeModemMessage) -> bool {
+        let frame_seq_number = msg.header.frame_seq_number;
+        match msg.header.secondary_header {
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::STATUS_RESPONSE) => {
+                let acknowledged = self.unacked_frames.remove(&frame_seq_number).is_some();
+                if acknowledged {
+                    trace!("Frame {} acknowledged", frame_seq_number);
+                }
+                !acknowledged
+            },
+            // Only the frames corrupted on the way are worth sending again
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::ERROR) if msg.error_code().is_framing_error() => {
+                if let Some(unacked) = self.unacked_frames.get_mut(&frame_seq_number) {
+                    trace!("Frame {} corrupted on the UART, retransmitting", frame_seq_number);
+                    unacked.deadline = Instant::now();
+                }
+                true
+            },
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::ERROR) => {
+                self.unacked_frames.remove(&frame_seq_number);
+                true
+            },
+            // Answered by the startup handshake
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::STARTUP_SYNC_REQUEST) => true,
+            _ => self.acknowledge(frame_seq_number)
+        }
+    }
+
+    // Returns false if the frame is one we had already acknowledged. It is acknowledged again
+    // anyway, as the module didn't get our first ACK.
+    fn acknowledge(&mut self, frame_seq_number: u8) -> bool {
+        if self.serial_ack.is_none() {
+            return true;
+        }
+        if let Err(e) = self.send_status_response(frame_seq_number, STATUS_SUCCESS) {
+            error!("Couldn't acknowledge frame {}: {:?}", frame_seq_number, e);
+        }
+        if self.last_received_seq_number == Some(frame_seq_number) {
+            trace!("Dropping frame {}, the module sent it again", frame_seq_number);
+            return false;
+        }
+        self.last_received_seq_number = Some(frame_seq_number);
+        true
+    }
+
+    // Error frames about a frame serial ACK mode is going to send again aren't final
+    fn is_retransmitting(&self, msg: &MmbZigbeeModemMessage) -> bool {
+        msg.error_code().is_framing_error() && self.unacked_frames.contains_key(&msg.header.frame_seq_number)
+    }
+
+    // Sends again every frame whose ACK timeout has expired. Returns the sequence numbers of
+    // the frames we gave up on, which are also reported as events.
+    fn retransmit_unacked_frames(&mut self) -> Vec<u8> {
+        let config = match self.serial_ack {
+            Some(config) => config,
+            None => return Vec::new()
+        };
+        let now = Instant::now();
+        let expired: Vec<u8> = self.unacked_frames.iter()
+            .filter(|&(_, unacked)| unacked.deadline <= now)
+            .map(|(&frame_seq_number, _)| frame_seq_number)
+            .collect();
+
+        let mut failed = Vec::new();
+        for frame_seq_number in expired {
+            let frame = match self.unacked_frames.get_mut(&frame_seq_number) {
+                Some(unacked) if unacked.retries_left > 0 => {
+                    unacked.retries_left -= 1;
+                    unacked.deadline = now + config.timeout;
+                    Some(unacked.frame.clone())
+                },
+                _ => None
+            };
+            match frame {
+                Some(frame) => {
+                    trace!("Retransmitting frame {}", frame_seq_number);
+                    if let Err(e) = self.write(&frame) {
+                        error!("Couldn't retransmit frame {}: {}", frame_seq_number, e);
+                    }
+                },
+                None => {
+                    error!("Frame {} was never acknowledged, giving up", frame_seq_number);
+                    self.unacked_frames.remove(&frame_seq_number);
+                    self.events.push_back(MmbZigbeeModemEvent::DeliveryFailed{ frame_seq_number });
+                    failed.push(frame_seq_number);
+                }
+            }
+        }
+        failed
+    }
+
+    // Returns the next complete frame from the modem, reading from the serial port if there's
+    // none buffered. Returns None if nothing arrived before the serial port read timeout.
+    fn next_received_frame(&mut self) -> Result<Option<MmbZigbeeModemMessage>, MmbZigbeeModemError> {
+        while let Some(frame) = self.reassembler.next_frame() {
+            match frame {
+                Ok(msg) => {
+                    if self.handle_serial_ack(&msg) {
+                        return Ok(Some(msg));
+                    }
+                },
+                Err(e) => error!("Discarding malformed frame: {:?}", e)
+            }
+        }
+
+        let mut buff = [0u8; 256];
+        let size = match self.serial_port {
+            Some(ref fd) => fd.borrow_mut().read(&mut buff),
+            None => Err(Error::other("Serial port to read not found!"))
+        };
+        match size {
+            Ok(0) => Err(MmbZigbeeModemError::Io(Error::new(ErrorKind::UnexpectedEof, "Serial port closed!"))),
+            Ok(size) => {
+                Self::print(&buff[..size]);
+                self.reassembler.push(&buff[..size]);
+                Ok(None)
+            },
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(MmbZigbeeModemError::Io(e))
+        }
+    }
+
+    // Blocks until `matcher` accepts a frame from the modem. Any other frame received in the
+    // meantime goes through the normal processing. Fails if `timeout` expires, or if the
+    // frame `frame_seq_number` we are waiting an answer for runs out of retransmissions.
+    fn wait_for_frame<F>(&mut self, frame_seq_number: Option<u8>, timeout: Duration, waiting_for: &'static str, matcher: F)
+        -> Result<MmbZigbeeModemMessage, MmbZigbeeModemError>
+        where F: Fn(&MmbZigbeeModemMessage) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let failed = self.run_timers();
+            if let Some(frame_seq_number) = frame_seq_number {
+                if failed.contains(&frame_seq_number) {
+                    return Err(MmbZigbeeModemError::NotAcknowledged{ frame_seq_number });
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(MmbZigbeeModemError::Timeout(waiting_for));
+            }
+            if let Some(msg) = self.next_received_frame()? {
+                if matcher(&msg) {
+                    return Ok(msg);
+                }
+                if let Some(frame_seq_number) = frame_seq_number {
+                    if msg.is_error(frame_seq_number) && !self.is_retransmitting(&msg) {
+                        return Err(MmbZigbeeModemError::CommandRejected{
+                            waiting_for,
+                            frame_seq_number,
+                            error: msg.error_code()
+                        });
+                    }
+                }
+                if let Err(msg) = self.process(&msg) {
+                    error!("Error parsing message from the UART: {}", msg);
+                }
+            }
+        }
+    }
+
+    // Blocks until `condition` holds, processing every frame received in the meantime
+    fn wait_until<F>(&mut self, timeout: Duration, waiting_for: &'static str, condition: F) -> Result<(), MmbZigbeeModemError>
+        where F: Fn(&MmbZigbeeModemProtocol) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !condition(self) {
+            self.run_timers();
+            if Instant::now() >= deadline {
+                return Err(MmbZigbeeModemError::Timeout(waiting_for));
+            }
+            if let Some(msg) = self.next_received_frame()? {
+                if let Err(msg) = self.process(&msg) {
+                    error!("Error parsing message from the UART: {}", msg);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
         match (&msg.header.primary_header, &msg.header.secondary_header) {
             (&PrimaryHeader::UTILITY_HEADER, &SecondaryHeader::HeaderUtilities(HeaderUtilities::STARTUP_SYNC_REQUEST)) => {
-                /*let mut _serial_port = match self.serial_port {
-                    Some(ref port) => port.borrow_mut(),
-                    None => return Err("No serial port!!".to_string())
-                };
-                MessageHandler::startup(&_serial_port, &msg)*/
-                let mut _serial_port = self.serial_port.clone().unwrap();
-                MessageHandler::startup(_serial_port, &msg)
+                self.on_startup_sync_request(msg)
             },
             (&PrimaryHeader::UTILITY_HEADER, &SecondaryHeader::HeaderUtilities(HeaderUtilities::STARTUP_SYNC_COMPLETE)) => {
-                Ok(())
+                self.on_startup_sync_complete()
             },
-            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::FORM_NETWORK))  => {
-                MessageHandler::form_network(&msg)
+            (&PrimaryHeader::UTILITY_HEADER, &SecondaryHeader::HeaderUtilities(HeaderUtilities::STATUS_RESPONSE)) => {
+                self.on_status_response(msg)
             },
-            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::JOIN_NETWORK))  => {
-                MessageHandler::join_network(&msg)
+            (&PrimaryHeader::UTILITY_HEADER, &SecondaryHeader::HeaderUtilities(HeaderUtilities::ERROR)) => {
+                self.on_error(msg)
             },
-            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::LEAVE_NETWORK))  => {
-                MessageHandler::not_implemented()
+            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::NETWORK_STATUS_RESPONSE))  => {
+                self.on_network_status(msg)
             },
-            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::NETWORK_AUTO_JOIN))  => {
-                MessageHandler::not_implemented()
+            (&PrimaryHeader::NETWORK_COMMISSIONING_HEADER, &SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::TRUST_CENTER_DEVICE_UPDATE))  => {
+                self.on_trust_center_device_update(msg)
             },
-            _ => Err("Unknown header!!".to_string())
+            (&PrimaryHeader::ZDO_MESSAGES_HEADER, &SecondaryHeader::HeaderZdoMessages(HeaderZdoMessages::ZDO_RESPONSE_RECEIVED))  => {
+                self.on_zdo_message(msg)
+            },
+            (&PrimaryHeader::ZCL_MESSAGES_HEADER, &SecondaryHeader::HeaderZclMessages(HeaderZclMessages::ZCL_MESSAGE_RECEIVED))  => {
+                self.on_zcl_message(msg)
+            },
+            (&PrimaryHeader::GENERAL_CLUSTERS_HEADER, &SecondaryHeader::HeaderGeneralClusters(HeaderGeneralClusters::CLUSTER_COMMAND_RECEIVED))  => {
+                self.on_cluster_command(msg)
+            },
+            (&PrimaryHeader::HA_CLUSTERS_HEADER, &SecondaryHeader::HeaderHaClusters(HeaderHaClusters::IAS_ZONE_ENROLL_REQUEST)) |
+            (&PrimaryHeader::HA_CLUSTERS_HEADER, &SecondaryHeader::HeaderHaClusters(HeaderHaClusters::IAS_ZONE_STATUS_CHANGE_NOTIFICATION)) |
+            (&PrimaryHeader::HA_CLUSTERS_HEADER, &SecondaryHeader::HeaderHaClusters(HeaderHaClusters::METER_READING_RESPONSE))  => {
+                self.on_ha_clusters_message(msg)
+            },
+            (&PrimaryHeader::OTA_BOOTLOAD_HEADER, &SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::QUERY_NEXT_IMAGE_REQUEST)) |
+            (&PrimaryHeader::OTA_BOOTLOAD_HEADER, &SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::IMAGE_BLOCK_REQUEST)) |
+            (&PrimaryHeader::OTA_BOOTLOAD_HEADER, &SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::UPGRADE_END_REQUEST))  => {
+                self.on_ota_message(msg)
+            },
+            _ => {
+                self.on_opaque_frame(msg);
+                Ok(())
+            }
         }
     }
 
-    fn write(&mut self, buff: &[u8]) -> Result<usize, Error> {
+    // Frames we don't know how to handle, like the ones newer firmware versions added, are
+    // passed on untouched so the application can deal with them.
+    fn on_opaque_frame(&mut self, msg: &MmbZigbeeModemMessage) {
+        let frame = OpaqueFrame {
+            primary_header: u8::from(msg.header.primary_header),
+            secondary_header: msg.header.secondary_header.to_u8(),
+            frame_seq_number: msg.header.frame_seq_number,
+            payload: msg.payload.clone()
+        };
+        trace!("Unhandled frame: {:?}", frame);
+        self.events.push_back(MmbZigbeeModemEvent::UnhandledFrame(frame));
+    }
+
+    fn write(&mut self, buff: &[u8]) -> Result<(), Error> {
         trace!("Sending: {:?} to modem", buff);
         match self.serial_port {
             Some(ref fd) => {
-                fd.borrow_mut().write(buff)
+                fd.borrow_mut().write_all(buff)
             }
-            None => Err(Error::new(ErrorKind::Other, "Serial port to write not found!"))
+            None => Err(Error::other("Serial port to write not found!"))
         }
     }
 
@@ -319,17 +1530,48 @@ impl MmbZigbeeModemProtocol {
 	}
 }
 impl SerialPortParser for MmbZigbeeModemProtocol {
-    fn parse(&self, buff : &[u8]) -> Result<(),()> {
+    type Event = MmbZigbeeModemEvent;
+
+    fn parse(&mut self, buff : &[u8]) -> Result<(),()> {
 		Self::print(buff);
-		let mmb_msg = MmbZigbeeModemMessage::new(buff).unwrap();
-        //self.process(&mmb_msg);
-        match self.process(&mmb_msg) {
-            Ok(_) => Ok(()),
-            Err(msg) => {
+        self.reassembler.push(buff);
+        let mut result = Ok(());
+        while let Some(frame) = self.reassembler.next_frame() {
+            let mmb_msg = match frame {
+                Ok(mmb_msg) => mmb_msg,
+                Err(e) => {
+                    error!("Discarding malformed frame: {:?}", e);
+                    result = Err(());
+                    continue;
+                }
+            };
+            if !self.handle_serial_ack(&mmb_msg) {
+                continue;
+            }
+            if let Err(msg) = self.process(&mmb_msg) {
                 error!("Error parsing message from the UART: {}", msg);
-                Err(())
+                result = Err(());
             }
         }
+        self.run_timers();
+        result
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.unacked_frames.values()
+            .map(|unacked| unacked.deadline)
+            .chain(self.startup_deadline)
+            .map(|deadline| if deadline > now { deadline - now } else { Duration::from_millis(0) })
+            .min()
+    }
+
+    fn on_timeout(&mut self) {
+        self.run_timers();
+    }
+
+    fn poll_event(&mut self) -> Option<MmbZigbeeModemEvent> {
+        MmbZigbeeModemProtocol::poll_event(self)
     }
 
     fn set_serial_port(&mut self,  serial_port: Rc<RefCell<ZigbeeSerialPort>>) {
@@ -337,3 +1579,191 @@ impl SerialPortParser for MmbZigbeeModemProtocol {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame_seq_number: u8, payload: &[u8]) -> Vec<u8> {
+        MmbZigbeeModemMessage::build(PrimaryHeader::UTILITY_HEADER,
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::MODULE_INFO_RESPONSE), frame_seq_number, payload)
+            .unwrap().to_bytes()
+    }
+
+    fn next_seq_number(reassembler: &mut MmbFrameReassembler) -> Option<u8> {
+        reassembler.next_frame().map(|frame| frame.unwrap().header.frame_seq_number)
+    }
+
+    #[test]
+    fn checksum_is_the_sum_of_the_bytes_after_start_of_frame() {
+        // Module Info Request, sequence number 1
+        assert_eq!(checksum(&[0x55, 0x02, 0x01, 0x00]), 0x0058);
+        // It wraps around at 16 bits
+        assert_eq!(checksum(&[0xFF; 300]), (300 * 0xFF % 0x10000) as u16);
+    }
+
+    #[test]
+    fn rejects_a_frame_with_a_bad_checksum() {
+        let bytes = [START_OF_FRAME, 0x55, 0x02, 0x01, 0x00, 0x59, 0x00];
+        match MmbZigbeeModemMessage::new(&bytes) {
+            Err(MmbZigbeeModemError::InvalidChecksum{ expected, received }) => {
+                assert_eq!(expected, 0x0058);
+                assert_eq!(received, 0x0059);
+            },
+            other => panic!("Expected a checksum error, got {:?}", other.map(|msg| msg.payload))
+        }
+        assert!(MmbZigbeeModemMessage::new(&[START_OF_FRAME, 0x55, 0x02, 0x01, 0x00, 0x58, 0x00]).is_ok());
+    }
+
+    #[test]
+    fn encodes_a_known_frame() {
+        let msg = MmbZigbeeModemMessage::build(PrimaryHeader::UTILITY_HEADER,
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::MODULE_INFO_REQUEST), 0x01, &[]).unwrap();
+        assert_eq!(msg.to_bytes(), vec![START_OF_FRAME, 0x55, 0x02, 0x01, 0x00, 0x58, 0x00]);
+    }
+
+    #[test]
+    fn encoded_frames_decode_back() {
+        let payload: Vec<u8> = (0..0xFF).collect();
+        let bytes = MmbZigbeeModemMessage::build(PrimaryHeader::NETWORK_COMMISSIONING_HEADER,
+            SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::FORM_NETWORK), 0xAB, &payload)
+            .unwrap().to_bytes();
+        let msg = MmbZigbeeModemMessage::new(&bytes).unwrap();
+        assert_eq!(msg.header.primary_header, PrimaryHeader::NETWORK_COMMISSIONING_HEADER);
+        assert_eq!(msg.header.secondary_header,
+            SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::FORM_NETWORK));
+        assert_eq!(msg.header.frame_seq_number, 0xAB);
+        assert_eq!(msg.payload, payload);
+        assert_eq!(msg.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn payloads_longer_than_a_frame_are_rejected() {
+        assert!(MmbZigbeeModemMessage::build(PrimaryHeader::UTILITY_HEADER,
+            SecondaryHeader::HeaderUtilities(HeaderUtilities::MODULE_INFO_REQUEST), 0, &[0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let mut protocol = MmbZigbeeModemProtocol::new();
+        protocol.frame_seq_number = 0xFF;
+        assert_eq!(protocol.next_frame_seq_number(), 0xFF);
+        assert_eq!(protocol.next_frame_seq_number(), 0x00);
+    }
+
+    #[test]
+    fn reassembles_a_frame_split_across_reads() {
+        let bytes = frame(1, &[1, 2, 3, 4]);
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&bytes[..3]);
+        assert!(reassembler.next_frame().is_none());
+        reassembler.push(&bytes[3..8]);
+        assert!(reassembler.next_frame().is_none());
+        reassembler.push(&bytes[8..]);
+        let msg = reassembler.next_frame().unwrap().unwrap();
+        assert_eq!(msg.payload, vec![1, 2, 3, 4]);
+        assert!(reassembler.next_frame().is_none());
+    }
+
+    #[test]
+    fn splits_several_frames_in_one_read() {
+        let mut bytes = frame(1, &[]);
+        bytes.extend(frame(2, &[0xF1, 0xF1]));
+        bytes.extend(frame(3, &[7]));
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&bytes);
+        assert_eq!(next_seq_number(&mut reassembler), Some(1));
+        assert_eq!(next_seq_number(&mut reassembler), Some(2));
+        assert_eq!(next_seq_number(&mut reassembler), Some(3));
+        assert_eq!(next_seq_number(&mut reassembler), None);
+    }
+
+    #[test]
+    fn drops_garbage_before_start_of_frame() {
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&[0x00, 0x13, 0x37]);
+        assert!(reassembler.next_frame().is_none());
+        assert!(reassembler.buffer.is_empty());
+
+        let mut bytes = vec![0xAA, 0x55, 0x00];
+        bytes.extend(frame(4, &[1]));
+        reassembler.push(&bytes);
+        assert_eq!(next_seq_number(&mut reassembler), Some(4));
+    }
+
+    #[test]
+    fn resynchronises_after_a_bad_checksum() {
+        let mut bytes = frame(5, &[1, 2]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        bytes.extend(frame(6, &[3]));
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&bytes);
+        match reassembler.next_frame() {
+            Some(Err(MmbZigbeeModemError::InvalidChecksum{ .. })) => {},
+            other => panic!("Expected a checksum error, got {:?}", other.map(|frame| frame.map(|msg| msg.header.frame_seq_number)))
+        }
+        assert_eq!(next_seq_number(&mut reassembler), Some(6));
+    }
+
+    #[test]
+    fn drops_a_stray_start_of_frame_before_a_frame() {
+        let mut bytes = vec![START_OF_FRAME, 0x77];
+        bytes.extend(frame(7, &[]));
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&bytes);
+        assert_eq!(next_seq_number(&mut reassembler), Some(7));
+        assert!(reassembler.next_frame().is_none());
+    }
+
+    #[test]
+    fn passes_on_frames_with_an_unknown_primary_header() {
+        let bytes = MmbZigbeeModemMessage::build(PrimaryHeader::Unknown(0x77), SecondaryHeader::Unknown(0x02), 9, &[1, 2])
+            .unwrap().to_bytes();
+        let mut protocol = MmbZigbeeModemProtocol::new();
+        assert!(protocol.parse(&bytes).is_ok());
+        match protocol.poll_event() {
+            Some(MmbZigbeeModemEvent::UnhandledFrame(frame)) => assert_eq!(frame, OpaqueFrame {
+                primary_header: 0x77,
+                secondary_header: 0x02,
+                frame_seq_number: 9,
+                payload: vec![1, 2]
+            }),
+            other => panic!("Expected the unhandled frame, got {:?}", other)
+        }
+        assert!(protocol.poll_event().is_none());
+    }
+
+    #[test]
+    fn a_bogus_payload_length_does_not_stall_the_next_frames() {
+        // Looks like a header announcing 255 bytes of payload
+        let mut bytes = vec![START_OF_FRAME, 0x55, 0x03, 0x00, 0xFF];
+        bytes.extend(frame(8, &[1]));
+        let mut reassembler = MmbFrameReassembler::new();
+        reassembler.push(&bytes);
+        assert_eq!(next_seq_number(&mut reassembler), Some(8));
+        assert!(reassembler.next_frame().is_none());
+    }
+
+    #[test]
+    fn header_bytes_without_a_command_decode_as_unknown() {
+        assert_eq!(HeaderHaClusters::from(0x03), HeaderHaClusters::Unknown(0x03));
+        assert_eq!(HeaderHaClusters::from(0x82), HeaderHaClusters::METER_READING_RESPONSE);
+        assert_eq!(HeaderBootload::from(0x01), HeaderBootload::Unknown(0x01));
+        assert_eq!(HeaderNetworkCommissioning::from(0x02), HeaderNetworkCommissioning::Unknown(0x02));
+        assert_eq!(HeaderUtilities::from(0x01), HeaderUtilities::Unknown(0x01));
+        assert_eq!(PrimaryHeader::from(0x00), PrimaryHeader::Unknown(0x00));
+        assert_eq!(SecondaryHeader::from(&PrimaryHeader::Unknown(0x77), 0x02), SecondaryHeader::Unknown(0x02));
+    }
+
+    #[test]
+    fn header_bytes_encode_back() {
+        for primary in 0..=0xFFu8 {
+            let primary_header = PrimaryHeader::from(primary);
+            assert_eq!(u8::from(primary_header), primary);
+            for secondary in 0..=0xFFu8 {
+                assert_eq!(SecondaryHeader::from(&primary_header, secondary).to_u8(), secondary);
+            }
+        }
+    }
+}
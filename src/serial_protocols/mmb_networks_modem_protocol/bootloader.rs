@@ -0,0 +1,195 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Error, ErrorKind};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemState,
+    MmbFrameReassembler, FirmwareVersion, PrimaryHeader, SecondaryHeader, HeaderBootload, RESPONSE_TIMEOUT};
+
+// Once in bootload mode the module runs the Ember standalone bootloader, which has a text
+// menu on the UART and receives the image with XMODEM-CRC.
+const BOOTLOADER_PROMPT: &str = "BL >";
+const MENU_UPLOAD: u8 = b'1';
+const MENU_RUN: u8 = b'2';
+
+const XMODEM_SOH: u8 = 0x01;
+const XMODEM_EOT: u8 = 0x04;
+const XMODEM_ACK: u8 = 0x06;
+const XMODEM_NAK: u8 = 0x15;
+const XMODEM_CAN: u8 = 0x18;
+const XMODEM_CRC_MODE: u8 = b'C';
+const XMODEM_BLOCK_SIZE: usize = 128;
+const XMODEM_PADDING: u8 = 0xFF;
+const XMODEM_MAX_RETRIES: u8 = 10;
+
+const BOOTLOADER_PROMPT_TIMEOUT: Duration = Duration::from_secs(10);
+const BOOTLOADER_MENU_INTERVAL: Duration = Duration::from_secs(1);
+const XMODEM_START_TIMEOUT: Duration = Duration::from_secs(10);
+const XMODEM_BLOCK_TIMEOUT: Duration = Duration::from_secs(5);
+// The new application has to boot and go through the startup handshake
+const APPLICATION_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootloadProgress {
+    EnteringBootloader,
+    Transferring{ sent: usize, total: usize },
+    // Waiting for the new application to start and checking its version
+    Verifying,
+    Done(FirmwareVersion)
+}
+
+// CRC-16/XMODEM: CCITT polynomial and 0 as initial value
+fn crc16_ccitt(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        let mut crc = crc ^ (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+impl MmbZigbeeModemProtocol {
+    // Flashes the .ebl image in `path`, which holds application `version`, and checks the
+    // module runs that version afterwards. If it doesn't (say it went back to the old firmware)
+    // the bootload failed. If the transfer fails the module stays in its bootloader, so calling
+    // this again retries it.
+    pub fn flash_firmware<P, F>(&mut self, path: P, version: FirmwareVersion, mut progress: F) -> Result<(), MmbZigbeeModemError>
+        where P: AsRef<Path>, F: FnMut(BootloadProgress) {
+        let mut image = Vec::new();
+        File::open(path).and_then(|mut file| file.read_to_end(&mut image)).map_err(MmbZigbeeModemError::Io)?;
+        if image.is_empty() {
+            return Err(MmbZigbeeModemError::BootloadFailed("The firmware image is empty"));
+        }
+
+        progress(BootloadProgress::EnteringBootloader);
+        // Otherwise we are probably retrying and the bootloader is already running
+        if self.state.is_initialized() {
+            self.enter_bootload_mode()?;
+        }
+        // From here on the module doesn't speak our protocol until the new application starts
+        self.set_state(MmbZigbeeModemState::UNINITIALIZED);
+        self.unacked_frames.clear();
+        self.startup_deadline = None;
+        self.reassembler = MmbFrameReassembler::new();
+        self.wait_for_bootloader_prompt()?;
+
+        self.write(&[MENU_UPLOAD]).map_err(MmbZigbeeModemError::Io)?;
+        if let Err(error) = self.xmodem_send(&image, &mut progress) {
+            // Makes the bootloader give up the transfer and go back to its menu
+            let _ = self.write(&[XMODEM_CAN, XMODEM_CAN, XMODEM_CAN]);
+            return Err(error);
+        }
+
+        self.wait_for_bootloader_prompt()?;
+        self.write(&[MENU_RUN]).map_err(MmbZigbeeModemError::Io)?;
+        progress(BootloadProgress::Verifying);
+        self.wait_for_startup(APPLICATION_STARTUP_TIMEOUT)?;
+        let running_version = self.application_version(0)?;
+        if running_version != version {
+            error!("Bootload: Expected application version {:?}, the module runs {:?}", version, running_version);
+            return Err(MmbZigbeeModemError::BootloadFailed("The module doesn't run the flashed application version"));
+        }
+        progress(BootloadProgress::Done(running_version));
+        Ok(())
+    }
+
+    fn enter_bootload_mode(&mut self) -> Result<(), MmbZigbeeModemError> {
+        let waiting_for = "Enter Bootload Mode status";
+        let frame_seq_number = self.send_command(PrimaryHeader::BOOTLOAD_HEADER,
+            SecondaryHeader::HeaderBootload(HeaderBootload::ENTER_BOOTLOAD_MODE), &[])?;
+        // Like on a restart, the module may jump to its bootloader before its Status Response
+        // makes it through the UART. The bootloader prompt tells whether it got there.
+        match self.wait_for_frame(Some(frame_seq_number), RESPONSE_TIMEOUT, waiting_for,
+            |msg| msg.is_status_response(frame_seq_number)) {
+            Ok(msg) => msg.check_status(waiting_for),
+            Err(MmbZigbeeModemError::Timeout(_)) => Ok(()),
+            Err(error) => Err(error)
+        }
+    }
+
+    fn wait_for_bootloader_prompt(&mut self) -> Result<(), MmbZigbeeModemError> {
+        let deadline = Instant::now() + BOOTLOADER_PROMPT_TIMEOUT;
+        let mut output = Vec::new();
+        while Instant::now() < deadline {
+            // An empty line makes the bootloader print its menu again
+            self.write(b"\r").map_err(MmbZigbeeModemError::Io)?;
+            let menu_deadline = cmp::min(deadline, Instant::now() + BOOTLOADER_MENU_INTERVAL);
+            while let Some(byte) = self.bootloader_read(menu_deadline)? {
+                output.push(byte);
+                if output.ends_with(BOOTLOADER_PROMPT.as_bytes()) {
+                    trace!("Bootloader: {}", String::from_utf8_lossy(&output));
+                    return Ok(());
+                }
+            }
+        }
+        Err(MmbZigbeeModemError::Timeout("bootloader prompt"))
+    }
+
+    fn xmodem_send<F>(&mut self, image: &[u8], progress: &mut F) -> Result<(), MmbZigbeeModemError>
+        where F: FnMut(BootloadProgress) {
+        // The receiver asks for CRC mode sending 'C's until the first block arrives
+        let deadline = Instant::now() + XMODEM_START_TIMEOUT;
+        loop {
+            match self.bootloader_read(deadline)? {
+                Some(XMODEM_CRC_MODE) => break,
+                Some(_) => continue,
+                None => return Err(MmbZigbeeModemError::Timeout("XMODEM transfer start"))
+            }
+        }
+
+        // [SOH][Block number][255 - Block number][Data: 128][CRC: 2, big endian]
+        for (index, data) in image.chunks(XMODEM_BLOCK_SIZE).enumerate() {
+            // Block numbers start at 1 and wrap around
+            let block_number = (index + 1) as u8;
+            let mut block = vec![XMODEM_SOH, block_number, !block_number];
+            block.extend_from_slice(data);
+            block.resize(3 + XMODEM_BLOCK_SIZE, XMODEM_PADDING);
+            let crc = crc16_ccitt(&block[3..]);
+            block.push((crc >> 8) as u8);
+            block.push(crc as u8);
+            self.xmodem_transfer(&block)?;
+            progress(BootloadProgress::Transferring{
+                sent: cmp::min(image.len(), (index + 1) * XMODEM_BLOCK_SIZE),
+                total: image.len()
+            });
+        }
+        self.xmodem_transfer(&[XMODEM_EOT])
+    }
+
+    // Sends a block (or EOT) until the receiver acknowledges it
+    fn xmodem_transfer(&mut self, block: &[u8]) -> Result<(), MmbZigbeeModemError> {
+        for _ in 0..XMODEM_MAX_RETRIES {
+            self.write(block).map_err(MmbZigbeeModemError::Io)?;
+            let deadline = Instant::now() + XMODEM_BLOCK_TIMEOUT;
+            loop {
+                match self.bootloader_read(deadline)? {
+                    Some(XMODEM_ACK) => return Ok(()),
+                    Some(XMODEM_CAN) => return Err(MmbZigbeeModemError::BootloadFailed("The bootloader cancelled the transfer")),
+                    Some(XMODEM_NAK) | None => break,
+                    // Leftovers of the 'C's asking for the first block
+                    Some(_) => continue
+                }
+            }
+        }
+        Err(MmbZigbeeModemError::BootloadFailed("Too many retransmissions"))
+    }
+
+    // The bootloader doesn't use frames, so this reads raw bytes. None once `deadline` has passed.
+    fn bootloader_read(&mut self, deadline: Instant) -> Result<Option<u8>, MmbZigbeeModemError> {
+        let mut buff = [0u8; 1];
+        while Instant::now() < deadline {
+            let size = match self.serial_port {
+                Some(ref fd) => fd.borrow_mut().read(&mut buff),
+                None => Err(Error::other("Serial port to read not found!"))
+            };
+            match size {
+                Ok(0) => return Err(MmbZigbeeModemError::Io(Error::new(ErrorKind::UnexpectedEof, "Serial port closed!"))),
+                Ok(_) => return Ok(Some(buff[0])),
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(MmbZigbeeModemError::Io(e))
+            }
+        }
+        Ok(None)
+    }
+}
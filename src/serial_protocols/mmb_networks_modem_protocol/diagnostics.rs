@@ -0,0 +1,182 @@
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, PrimaryHeader,
+    SecondaryHeader, HeaderDiagnostics, PayloadReader, NodeType};
+
+// Sent and received frames of one layer of the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrafficCounters {
+    pub rx_broadcast: u16,
+    pub tx_broadcast: u16,
+    pub rx_unicast: u16,
+    pub tx_unicast_success: u16,
+    pub tx_unicast_retry: u16,
+    pub tx_unicast_failed: u16
+}
+impl TrafficCounters {
+    fn read(reader: &mut PayloadReader) -> Result<TrafficCounters, MmbZigbeeModemError> {
+        Ok(TrafficCounters {
+            rx_broadcast: reader.u16()?,
+            tx_broadcast: reader.u16()?,
+            rx_unicast: reader.u16()?,
+            tx_unicast_success: reader.u16()?,
+            tx_unicast_retry: reader.u16()?,
+            tx_unicast_failed: reader.u16()?
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkCounters {
+    pub route_discovery_initiated: u16,
+    pub neighbor_added: u16,
+    pub neighbor_removed: u16,
+    pub neighbor_stale: u16,
+    pub join_indication: u16,
+    pub child_moved: u16,
+    pub frame_counter_failure: u16,
+    pub decryption_failure: u16
+}
+impl NetworkCounters {
+    fn read(reader: &mut PayloadReader) -> Result<NetworkCounters, MmbZigbeeModemError> {
+        Ok(NetworkCounters {
+            route_discovery_initiated: reader.u16()?,
+            neighbor_added: reader.u16()?,
+            neighbor_removed: reader.u16()?,
+            neighbor_stale: reader.u16()?,
+            join_indication: reader.u16()?,
+            child_moved: reader.u16()?,
+            frame_counter_failure: reader.u16()?,
+            decryption_failure: reader.u16()?
+        })
+    }
+}
+
+// The counters saturate at 0xFFFF instead of wrapping around
+// [MAC: 6 * 2][NWK: 8 * 2][APS: 6 * 2]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiagnosticCounters {
+    pub mac: TrafficCounters,
+    pub nwk: NetworkCounters,
+    pub aps: TrafficCounters
+}
+
+// Of the last frame received by the module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkQuality {
+    pub lqi: u8,
+    // dBm
+    pub rssi: i8
+}
+
+// [Short address: 2][EUI64: 8][Average LQI: 1][Incoming cost: 1][Outgoing cost: 1][Age: 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborTableEntry {
+    pub short_address: u16,
+    pub eui64: u64,
+    pub average_lqi: u8,
+    // Link costs go from 1 (best) to 7, 0 means unknown
+    pub incoming_cost: u8,
+    pub outgoing_cost: u8,
+    // Link status periods since we last heard from the neighbor
+    pub age: u8
+}
+impl NeighborTableEntry {
+    fn read(reader: &mut PayloadReader) -> Result<NeighborTableEntry, MmbZigbeeModemError> {
+        Ok(NeighborTableEntry {
+            short_address: reader.u16()?,
+            eui64: reader.u64()?,
+            average_lqi: reader.u8()?,
+            incoming_cost: reader.u8()?,
+            outgoing_cost: reader.u8()?,
+            age: reader.u8()?
+        })
+    }
+}
+
+// [Short address: 2][EUI64: 8][Node type: 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildTableEntry {
+    pub short_address: u16,
+    pub eui64: u64,
+    pub node_type: NodeType
+}
+impl ChildTableEntry {
+    fn read(reader: &mut PayloadReader) -> Result<ChildTableEntry, MmbZigbeeModemError> {
+        Ok(ChildTableEntry {
+            short_address: reader.u16()?,
+            eui64: reader.u64()?,
+            node_type: NodeType::from(reader.u8()?)
+        })
+    }
+}
+
+impl MmbZigbeeModemProtocol {
+    pub fn diagnostic_counters(&mut self) -> Result<DiagnosticCounters, MmbZigbeeModemError> {
+        let payload = self.diagnostics_request(HeaderDiagnostics::COUNTERS_REQUEST, HeaderDiagnostics::COUNTERS_RESPONSE,
+            &[], "Counters Response")?;
+        let mut reader = PayloadReader::new(&payload);
+        Ok(DiagnosticCounters {
+            mac: TrafficCounters::read(&mut reader)?,
+            nwk: NetworkCounters::read(&mut reader)?,
+            aps: TrafficCounters::read(&mut reader)?
+        })
+    }
+
+    pub fn reset_diagnostic_counters(&mut self) -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::DIAGNOSTICS_HEADER, SecondaryHeader::HeaderDiagnostics(HeaderDiagnostics::RESET_COUNTERS),
+            &[], "Reset Counters status")
+    }
+
+    pub fn last_hop_link_quality(&mut self) -> Result<LinkQuality, MmbZigbeeModemError> {
+        // [LQI: 1][RSSI: 1]
+        let payload = self.diagnostics_request(HeaderDiagnostics::LAST_HOP_INFO_REQUEST, HeaderDiagnostics::LAST_HOP_INFO_RESPONSE,
+            &[], "Last Hop Info Response")?;
+        let mut reader = PayloadReader::new(&payload);
+        Ok(LinkQuality {
+            lqi: reader.u8()?,
+            rssi: reader.i8()?
+        })
+    }
+
+    pub fn neighbor_table(&mut self) -> Result<Vec<NeighborTableEntry>, MmbZigbeeModemError> {
+        self.diagnostics_table(HeaderDiagnostics::NEIGHBOR_TABLE_REQUEST, HeaderDiagnostics::NEIGHBOR_TABLE_RESPONSE,
+            "Neighbor Table Response", NeighborTableEntry::read)
+    }
+
+    pub fn child_table(&mut self) -> Result<Vec<ChildTableEntry>, MmbZigbeeModemError> {
+        self.diagnostics_table(HeaderDiagnostics::CHILD_TABLE_REQUEST, HeaderDiagnostics::CHILD_TABLE_RESPONSE,
+            "Child Table Response", ChildTableEntry::read)
+    }
+
+    // Tables don't fit in a single frame, so they are read a page at a time.
+    // Request: [Start index: 1]
+    // Response: [Total entries: 1][Start index: 1][Entry count: 1][Entries]
+    fn diagnostics_table<T, F>(&mut self, request: HeaderDiagnostics, response: HeaderDiagnostics, waiting_for: &'static str,
+        read_entry: F) -> Result<Vec<T>, MmbZigbeeModemError>
+        where F: Fn(&mut PayloadReader) -> Result<T, MmbZigbeeModemError> {
+        let mut entries = Vec::new();
+        loop {
+            let start_index = entries.len() as u8;
+            let payload = self.diagnostics_request(request, response, &[start_index], waiting_for)?;
+            let mut reader = PayloadReader::new(&payload);
+            let total_entries = reader.u8()?;
+            if reader.u8()? != start_index {
+                return Err(MmbZigbeeModemError::new("Message format error: Table page for another index"));
+            }
+            let count = reader.u8()?;
+            for _ in 0..count {
+                entries.push(read_entry(&mut reader)?);
+            }
+            // An empty page would make us loop forever if the table shrank meanwhile
+            if count == 0 || entries.len() >= total_entries as usize {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn diagnostics_request(&mut self, request: HeaderDiagnostics, response: HeaderDiagnostics, payload: &[u8],
+        waiting_for: &'static str) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let msg = self.transact(PrimaryHeader::DIAGNOSTICS_HEADER, SecondaryHeader::HeaderDiagnostics(request),
+            SecondaryHeader::HeaderDiagnostics(response), payload, waiting_for)?;
+        Ok(msg.payload)
+    }
+}
@@ -0,0 +1,193 @@
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    MmbZigbeeModemEvent, PrimaryHeader, SecondaryHeader, HeaderGeneralClusters, PayloadReader, PayloadWriter, Destination};
+use serial_protocols::mmb_networks_modem_protocol::zcl_messages::{read_zcl_message, ZclFrame};
+
+const BASIC_CLUSTER_ID: u16 = 0x0000;
+const IDENTIFY_CLUSTER_ID: u16 = 0x0003;
+const SCENES_CLUSTER_ID: u16 = 0x0005;
+const ON_OFF_CLUSTER_ID: u16 = 0x0006;
+const LEVEL_CONTROL_CLUSTER_ID: u16 = 0x0008;
+
+// Group names are optional and most devices don't store them
+const MAX_GROUP_NAME_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnOffCommand {
+    OFF = 0x00,
+    ON = 0x01,
+    TOGGLE = 0x02,
+}
+
+byte_enum! {
+    pub enum LevelDirection {
+        UP = 0x00,
+        DOWN = 0x01,
+    }
+}
+
+// Cluster commands other devices (switches, remotes...) send to the host endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeneralClusterCommand {
+    ResetToFactoryDefaults,
+    Identify{ duration: u16 },
+    IdentifyQuery,
+    OnOff(OnOffCommand),
+    // Times are in tenths of a second
+    MoveToLevel{ level: u8, transition_time: u16, with_on_off: bool },
+    MoveLevel{ direction: LevelDirection, rate: u8, with_on_off: bool },
+    StepLevel{ direction: LevelDirection, step_size: u8, transition_time: u16, with_on_off: bool },
+    StopLevel,
+    RecallScene{ group_id: u16, scene_id: u8 },
+    Unknown{ command_id: u8, payload: Vec<u8> }
+}
+impl GeneralClusterCommand {
+    fn read(cluster_id: u16, frame: &ZclFrame) -> Result<GeneralClusterCommand, MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(&frame.payload);
+        Ok(match (cluster_id, frame.command_id) {
+            (BASIC_CLUSTER_ID, 0x00) => GeneralClusterCommand::ResetToFactoryDefaults,
+            (IDENTIFY_CLUSTER_ID, 0x00) => GeneralClusterCommand::Identify{ duration: reader.u16()? },
+            (IDENTIFY_CLUSTER_ID, 0x01) => GeneralClusterCommand::IdentifyQuery,
+            (ON_OFF_CLUSTER_ID, 0x00) => GeneralClusterCommand::OnOff(OnOffCommand::OFF),
+            (ON_OFF_CLUSTER_ID, 0x01) => GeneralClusterCommand::OnOff(OnOffCommand::ON),
+            (ON_OFF_CLUSTER_ID, 0x02) => GeneralClusterCommand::OnOff(OnOffCommand::TOGGLE),
+            // 0x04 to 0x07 are the "with On/Off" versions of 0x00 to 0x03
+            (LEVEL_CONTROL_CLUSTER_ID, command_id @ 0x00..=0x07) => {
+                let with_on_off = command_id & 0x04 != 0;
+                match command_id & 0x03 {
+                    0x00 => GeneralClusterCommand::MoveToLevel{
+                        level: reader.u8()?,
+                        transition_time: reader.u16()?,
+                        with_on_off
+                    },
+                    0x01 => GeneralClusterCommand::MoveLevel{
+                        direction: LevelDirection::from(reader.u8()?),
+                        rate: reader.u8()?,
+                        with_on_off
+                    },
+                    0x02 => GeneralClusterCommand::StepLevel{
+                        direction: LevelDirection::from(reader.u8()?),
+                        step_size: reader.u8()?,
+                        transition_time: reader.u16()?,
+                        with_on_off
+                    },
+                    _ => GeneralClusterCommand::StopLevel
+                }
+            },
+            (SCENES_CLUSTER_ID, 0x05) => GeneralClusterCommand::RecallScene{ group_id: reader.u16()?, scene_id: reader.u8()? },
+            (_, command_id) => GeneralClusterCommand::Unknown{ command_id, payload: frame.payload.clone() }
+        })
+    }
+}
+
+impl MmbZigbeeModemProtocol {
+    // Basic cluster
+    pub fn reset_to_factory_defaults(&mut self, destination: Destination) -> Result<(), MmbZigbeeModemError> {
+        self.general_clusters_command(HeaderGeneralClusters::RESET_TO_FACTORY_DEFAULTS, destination, &[],
+            "Reset To Factory Defaults status")
+    }
+
+    // Identify cluster. Duration is in seconds, 0 stops identifying.
+    pub fn identify(&mut self, destination: Destination, duration: u16) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(duration);
+        self.general_clusters_command(HeaderGeneralClusters::IDENTIFY, destination, &payload.into_vec(), "Identify status")
+    }
+
+    // On/Off cluster
+    pub fn on_off(&mut self, destination: Destination, command: OnOffCommand) -> Result<(), MmbZigbeeModemError> {
+        self.general_clusters_command(HeaderGeneralClusters::ON_OFF, destination, &[command as u8], "On/Off status")
+    }
+
+    // Level Control cluster. Transition times are in tenths of a second, rates in units per second.
+    pub fn move_to_level(&mut self, destination: Destination, level: u8, transition_time: u16, with_on_off: bool)
+        -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u8(with_on_off as u8).u8(level).u16(transition_time);
+        self.general_clusters_command(HeaderGeneralClusters::MOVE_TO_LEVEL, destination, &payload.into_vec(), "Move To Level status")
+    }
+
+    pub fn move_level(&mut self, destination: Destination, direction: LevelDirection, rate: u8, with_on_off: bool)
+        -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u8(with_on_off as u8).u8(u8::from(direction)).u8(rate);
+        self.general_clusters_command(HeaderGeneralClusters::MOVE_LEVEL, destination, &payload.into_vec(), "Move status")
+    }
+
+    pub fn step_level(&mut self, destination: Destination, direction: LevelDirection, step_size: u8, transition_time: u16,
+        with_on_off: bool) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u8(with_on_off as u8).u8(u8::from(direction)).u8(step_size).u16(transition_time);
+        self.general_clusters_command(HeaderGeneralClusters::STEP_LEVEL, destination, &payload.into_vec(), "Step status")
+    }
+
+    pub fn stop_level(&mut self, destination: Destination) -> Result<(), MmbZigbeeModemError> {
+        self.general_clusters_command(HeaderGeneralClusters::STOP_LEVEL, destination, &[], "Stop status")
+    }
+
+    // Groups cluster
+    pub fn add_group(&mut self, destination: Destination, group_id: u16, name: &str) -> Result<(), MmbZigbeeModemError> {
+        if name.len() > MAX_GROUP_NAME_SIZE {
+            return Err(MmbZigbeeModemError::new("Add Group: Group name too long"));
+        }
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id).u8(name.len() as u8).bytes(name.as_bytes());
+        self.general_clusters_command(HeaderGeneralClusters::ADD_GROUP, destination, &payload.into_vec(), "Add Group status")
+    }
+
+    pub fn remove_group(&mut self, destination: Destination, group_id: u16) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id);
+        self.general_clusters_command(HeaderGeneralClusters::REMOVE_GROUP, destination, &payload.into_vec(), "Remove Group status")
+    }
+
+    pub fn remove_all_groups(&mut self, destination: Destination) -> Result<(), MmbZigbeeModemError> {
+        self.general_clusters_command(HeaderGeneralClusters::REMOVE_ALL_GROUPS, destination, &[], "Remove All Groups status")
+    }
+
+    // Scenes cluster
+    pub fn store_scene(&mut self, destination: Destination, group_id: u16, scene_id: u8) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id).u8(scene_id);
+        self.general_clusters_command(HeaderGeneralClusters::STORE_SCENE, destination, &payload.into_vec(), "Store Scene status")
+    }
+
+    pub fn recall_scene(&mut self, destination: Destination, group_id: u16, scene_id: u8) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id).u8(scene_id);
+        self.general_clusters_command(HeaderGeneralClusters::RECALL_SCENE, destination, &payload.into_vec(), "Recall Scene status")
+    }
+
+    pub fn remove_scene(&mut self, destination: Destination, group_id: u16, scene_id: u8) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id).u8(scene_id);
+        self.general_clusters_command(HeaderGeneralClusters::REMOVE_SCENE, destination, &payload.into_vec(), "Remove Scene status")
+    }
+
+    pub fn remove_all_scenes(&mut self, destination: Destination, group_id: u16) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u16(group_id);
+        self.general_clusters_command(HeaderGeneralClusters::REMOVE_ALL_SCENES, destination, &payload.into_vec(),
+            "Remove All Scenes status")
+    }
+
+    // [Address mode: 1][Destination: 2][Destination endpoint: 1][Source endpoint: 1][Command payload]
+    fn general_clusters_command(&mut self, command: HeaderGeneralClusters, destination: Destination, payload: &[u8],
+        waiting_for: &'static str) -> Result<(), MmbZigbeeModemError> {
+        let mut message = PayloadWriter::new();
+        destination.write(&mut message);
+        message.u8(self.zcl_source_endpoint).bytes(payload);
+        self.execute(PrimaryHeader::GENERAL_CLUSTERS_HEADER, SecondaryHeader::HeaderGeneralClusters(command),
+            &message.into_vec(), waiting_for)
+    }
+
+    // The module forwards the cluster commands sent to the host endpoint as
+    // [Source: 2][Source endpoint: 1][Destination endpoint: 1][Profile ID: 2][Cluster ID: 2][ZCL frame]
+    pub(super) fn on_cluster_command(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let (source, frame) = read_zcl_message(&msg.payload).map_err(|e| format!("{:?}", e))?;
+        let command = GeneralClusterCommand::read(source.cluster_id, &frame).map_err(|e| format!("{:?}", e))?;
+        trace!("Cluster command from {:?}: {:?}", source, command);
+        self.events.push_back(MmbZigbeeModemEvent::ClusterCommandReceived(source, command));
+        Ok(())
+    }
+}
+
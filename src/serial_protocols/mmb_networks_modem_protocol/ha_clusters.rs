@@ -0,0 +1,182 @@
+use std::time::Duration;
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    MmbZigbeeModemEvent, PrimaryHeader, SecondaryHeader, HeaderHaClusters, PayloadReader, PayloadWriter, Destination};
+
+// Meters are usually sleepy, battery powered devices
+const METER_READING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetpointMode {
+    HEAT = 0x00,
+    COOL = 0x01,
+    BOTH = 0x02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum ZoneEnrollResponseCode {
+    SUCCESS = 0x00,
+    NOT_SUPPORTED = 0x01,
+    NO_ENROLL_PERMIT = 0x02,
+    TOO_MANY_ZONES = 0x03,
+}
+
+// [Source: 2][Source endpoint: 1][Zone type: 2][Manufacturer code: 2]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneEnrollRequest {
+    pub short_address: u16,
+    pub endpoint: u8,
+    pub zone_type: u16,
+    pub manufacturer_code: u16
+}
+impl ZoneEnrollRequest {
+    fn read(reader: &mut PayloadReader) -> Result<ZoneEnrollRequest, MmbZigbeeModemError> {
+        Ok(ZoneEnrollRequest {
+            short_address: reader.u16()?,
+            endpoint: reader.u8()?,
+            zone_type: reader.u16()?,
+            manufacturer_code: reader.u16()?
+        })
+    }
+}
+
+// [Source: 2][Source endpoint: 1][Zone status: 2][Extended status: 1][Zone ID: 1][Delay: 2]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneStatusChange {
+    pub short_address: u16,
+    pub endpoint: u8,
+    pub zone_status: u16,
+    pub extended_status: u8,
+    pub zone_id: u8,
+    // Quarters of a second
+    pub delay: u16
+}
+impl ZoneStatusChange {
+    fn read(reader: &mut PayloadReader) -> Result<ZoneStatusChange, MmbZigbeeModemError> {
+        Ok(ZoneStatusChange {
+            short_address: reader.u16()?,
+            endpoint: reader.u8()?,
+            zone_status: reader.u16()?,
+            extended_status: reader.u8()?,
+            zone_id: reader.u8()?,
+            delay: reader.u16()?
+        })
+    }
+
+    pub fn alarm1(&self) -> bool {
+        self.zone_status & 0x0001 != 0
+    }
+
+    pub fn alarm2(&self) -> bool {
+        self.zone_status & 0x0002 != 0
+    }
+
+    pub fn tamper(&self) -> bool {
+        self.zone_status & 0x0004 != 0
+    }
+
+    pub fn battery_low(&self) -> bool {
+        self.zone_status & 0x0008 != 0
+    }
+}
+
+// [Source: 2][Source endpoint: 1][Current summation delivered: 6][Instantaneous demand: 3 signed]
+// [Multiplier: 3][Divisor: 3][Unit of measure: 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeterReading {
+    pub short_address: u16,
+    pub endpoint: u8,
+    pub current_summation_delivered: u64,
+    pub instantaneous_demand: i32,
+    pub multiplier: u32,
+    pub divisor: u32,
+    // 0x00 kWh, 0x01 m3, 0x02 ft3... see the Metering cluster
+    pub unit_of_measure: u8
+}
+impl MeterReading {
+    fn read(reader: &mut PayloadReader) -> Result<MeterReading, MmbZigbeeModemError> {
+        Ok(MeterReading {
+            short_address: reader.u16()?,
+            endpoint: reader.u8()?,
+            current_summation_delivered: reader.uint(6)?,
+            instantaneous_demand: reader.int(3)? as i32,
+            multiplier: reader.uint(3)? as u32,
+            divisor: reader.uint(3)? as u32,
+            unit_of_measure: reader.u8()?
+        })
+    }
+
+    // Raw values have to be multiplied and divided to get real units. A zero in any of
+    // them means the meter doesn't use it.
+    fn scale(&self, value: f64) -> f64 {
+        let multiplier = if self.multiplier == 0 { 1 } else { self.multiplier };
+        let divisor = if self.divisor == 0 { 1 } else { self.divisor };
+        value * multiplier as f64 / divisor as f64
+    }
+
+    pub fn summation_delivered(&self) -> f64 {
+        self.scale(self.current_summation_delivered as f64)
+    }
+
+    pub fn demand(&self) -> f64 {
+        self.scale(self.instantaneous_demand as f64)
+    }
+}
+
+impl MmbZigbeeModemProtocol {
+    // Amount is in tenths of a degree Celsius, negative to lower the setpoint
+    pub fn setpoint_raise_lower(&mut self, destination: Destination, mode: SetpointMode, amount: i8) -> Result<(), MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        payload.u8(mode as u8).i8(amount);
+        self.ha_clusters_command(HeaderHaClusters::THERMOSTAT_SETPOINT_RAISE_LOWER, destination, &payload.into_vec(),
+            "Setpoint Raise/Lower status")
+    }
+
+    // Answer to a ZoneEnrollRequested event
+    pub fn zone_enroll_response(&mut self, short_address: u16, endpoint: u8, response_code: ZoneEnrollResponseCode, zone_id: u8)
+        -> Result<(), MmbZigbeeModemError> {
+        let destination = Destination::Unicast{ short_address, endpoint };
+        self.ha_clusters_command(HeaderHaClusters::IAS_ZONE_ENROLL_RESPONSE, destination, &[response_code as u8, zone_id],
+            "Zone Enroll Response status")
+    }
+
+    pub fn meter_reading(&mut self, short_address: u16, endpoint: u8) -> Result<MeterReading, MmbZigbeeModemError> {
+        let destination = Destination::Unicast{ short_address, endpoint };
+        self.ha_clusters_command(HeaderHaClusters::METER_READING_REQUEST, destination, &[], "Meter Reading Request status")?;
+
+        let meter_reading_response = SecondaryHeader::HeaderHaClusters(HeaderHaClusters::METER_READING_RESPONSE);
+        let msg = self.wait_for_frame(None, METER_READING_TIMEOUT, "Meter Reading Response", |msg| {
+            // [Source: 2][Source endpoint: 1]...
+            msg.header.secondary_header == meter_reading_response && msg.payload.len() >= 3 &&
+                msg.payload[0] as u16 | (msg.payload[1] as u16) << 8 == short_address && msg.payload[2] == endpoint
+        })?;
+        MeterReading::read(&mut PayloadReader::new(&msg.payload))
+    }
+
+    // [Address mode: 1][Destination: 2][Destination endpoint: 1][Source endpoint: 1][Command payload]
+    fn ha_clusters_command(&mut self, command: HeaderHaClusters, destination: Destination, payload: &[u8],
+        waiting_for: &'static str) -> Result<(), MmbZigbeeModemError> {
+        let mut message = PayloadWriter::new();
+        destination.write(&mut message);
+        message.u8(self.zcl_source_endpoint).bytes(payload);
+        self.execute(PrimaryHeader::HA_CLUSTERS_HEADER, SecondaryHeader::HeaderHaClusters(command),
+            &message.into_vec(), waiting_for)
+    }
+
+    pub(super) fn on_ha_clusters_message(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let mut reader = PayloadReader::new(&msg.payload);
+        let event = match msg.header.secondary_header {
+            SecondaryHeader::HeaderHaClusters(HeaderHaClusters::IAS_ZONE_ENROLL_REQUEST) =>
+                ZoneEnrollRequest::read(&mut reader).map(MmbZigbeeModemEvent::ZoneEnrollRequested),
+            SecondaryHeader::HeaderHaClusters(HeaderHaClusters::IAS_ZONE_STATUS_CHANGE_NOTIFICATION) =>
+                ZoneStatusChange::read(&mut reader).map(MmbZigbeeModemEvent::ZoneStatusChanged),
+            // Unsolicited, or arriving after meter_reading() gave up
+            SecondaryHeader::HeaderHaClusters(HeaderHaClusters::METER_READING_RESPONSE) =>
+                MeterReading::read(&mut reader).map(MmbZigbeeModemEvent::MeterReadingReceived),
+            _ => return Err("Unknown HA clusters message".to_string())
+        }.map_err(|e| format!("{:?}", e))?;
+        trace!("HA clusters: {:?}", event);
+        self.events.push_back(event);
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use std::time::{Duration, Instant};
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    MmbZigbeeModemEvent, MmbZigbeeModemState, PrimaryHeader, SecondaryHeader, HeaderNetworkCommissioning, PayloadReader, PayloadWriter};
+
+// Energy scan plus PAN ID selection can take a while on a busy spectrum
+const FORM_NETWORK_TIMEOUT: Duration = Duration::from_secs(30);
+// Joining means scanning every channel of the mask, and then the key exchange
+const JOIN_NETWORK_TIMEOUT: Duration = Duration::from_secs(60);
+const LEAVE_NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Auto options of the Form/Join Network commands: let the stack pick (or accept any)
+// PAN ID and extended PAN ID
+const AUTO_PAN_ID: u8 = 0x01;
+const AUTO_EXTENDED_PAN_ID: u8 = 0x02;
+
+byte_enum! {
+    pub enum NetworkState {
+        NOT_JOINED = 0x00,
+        JOINED = 0x01,
+        JOINING = 0x02,
+        JOINED_NO_PARENT = 0x03,
+        LEAVING = 0x04,
+    }
+}
+
+byte_enum! {
+    pub enum NodeType {
+        COORDINATOR = 0x00,
+        ROUTER = 0x01,
+        END_DEVICE = 0x02,
+        SLEEPY_END_DEVICE = 0x03,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkParameters {
+    pub channel: u8,
+    pub pan_id: u16,
+    pub extended_pan_id: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkStatus {
+    pub state: NetworkState,
+    pub node_type: NodeType,
+    pub channel: u8,
+    pub short_address: u16,
+    pub pan_id: u16,
+    pub extended_pan_id: u64
+}
+impl NetworkStatus {
+    // [State: 1][Node type: 1][Channel: 1][Short address: 2][PAN ID: 2][Extended PAN ID: 8]
+    fn new(payload: &[u8]) -> Result<NetworkStatus, MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(payload);
+        Ok(NetworkStatus {
+            state: NetworkState::from(reader.u8()?),
+            node_type: NodeType::from(reader.u8()?),
+            channel: reader.u8()?,
+            short_address: reader.u16()?,
+            pan_id: reader.u16()?,
+            extended_pan_id: reader.u64()?
+        })
+    }
+
+    pub fn parameters(&self) -> NetworkParameters {
+        NetworkParameters {
+            channel: self.channel,
+            pan_id: self.pan_id,
+            extended_pan_id: self.extended_pan_id
+        }
+    }
+}
+
+// Trust Center Device Update status, as in the APS Update-Device command
+byte_enum! {
+    pub enum DeviceUpdateStatus {
+        SECURED_REJOIN = 0x00,
+        UNSECURED_JOIN = 0x01,
+        LEFT = 0x02,
+        TRUST_CENTER_REJOIN = 0x03,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceUpdate {
+    pub eui64: u64,
+    pub short_address: u16,
+    pub status: DeviceUpdateStatus
+}
+impl DeviceUpdate {
+    // [EUI64: 8][Short address: 2][Status: 1]
+    fn new(payload: &[u8]) -> Result<DeviceUpdate, MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(payload);
+        Ok(DeviceUpdate {
+            eui64: reader.u64()?,
+            short_address: reader.u16()?,
+            status: DeviceUpdateStatus::from(reader.u8()?)
+        })
+    }
+}
+
+// Which networks the module may join on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinPolicy {
+    // The first network that permits joining
+    AnyNetwork,
+    // Only the network with this extended PAN ID
+    ExtendedPanId(u64)
+}
+
+// The module is still working on a commissioning command
+fn is_in_progress(state: NetworkState) -> bool {
+    matches!(state, NetworkState::JOINING | NetworkState::JOINED_NO_PARENT | NetworkState::LEAVING)
+}
+
+fn auto_options(pan_id: Option<u16>, extended_pan_id: Option<u64>) -> u8 {
+    let mut auto_options = 0;
+    if pan_id.is_none() {
+        auto_options |= AUTO_PAN_ID;
+    }
+    if extended_pan_id.is_none() {
+        auto_options |= AUTO_EXTENDED_PAN_ID;
+    }
+    auto_options
+}
+
+impl MmbZigbeeModemProtocol {
+    // Forms a new network as coordinator on one of the channels of `channel_mask`. The stack
+    // picks the PAN ID and extended PAN ID that aren't given. Returns the parameters of the
+    // network once it's up.
+    pub fn form_network(&mut self, channel_mask: u32, pan_id: Option<u16>, extended_pan_id: Option<u64>, tx_power: i8)
+        -> Result<NetworkParameters, MmbZigbeeModemError> {
+        // [Channel mask: 4][Auto options: 1][PAN ID: 2][Extended PAN ID: 8][TX power: 1]
+        let mut payload = PayloadWriter::new();
+        payload.u32(channel_mask)
+            .u8(auto_options(pan_id, extended_pan_id))
+            .u16(pan_id.unwrap_or(0))
+            .u64(extended_pan_id.unwrap_or(0))
+            .i8(tx_power);
+
+        let status = self.commission(HeaderNetworkCommissioning::FORM_NETWORK, &payload.into_vec(), "Form Network status",
+            FORM_NETWORK_TIMEOUT, "Network Status after Form Network", NetworkState::JOINED)?;
+        match status.state {
+            NetworkState::JOINED => Ok(status.parameters()),
+            _ => Err(MmbZigbeeModemError::new("Form Network: The network couldn't be formed"))
+        }
+    }
+
+    // Joins an existing network as router or (sleepy) end device, scanning the channels of
+    // `channel_mask`. Any PAN ID and extended PAN ID are accepted if they aren't given.
+    pub fn join_network(&mut self, node_type: NodeType, channel_mask: u32, pan_id: Option<u16>, extended_pan_id: Option<u64>)
+        -> Result<NetworkStatus, MmbZigbeeModemError> {
+        match node_type {
+            NodeType::ROUTER | NodeType::END_DEVICE | NodeType::SLEEPY_END_DEVICE => {},
+            _ => return Err(MmbZigbeeModemError::new("Join Network: Only routers and end devices can join a network"))
+        }
+        // [Node type: 1][Channel mask: 4][Auto options: 1][PAN ID: 2][Extended PAN ID: 8]
+        let mut payload = PayloadWriter::new();
+        payload.u8(u8::from(node_type))
+            .u32(channel_mask)
+            .u8(auto_options(pan_id, extended_pan_id))
+            .u16(pan_id.unwrap_or(0))
+            .u64(extended_pan_id.unwrap_or(0));
+
+        let status = self.commission(HeaderNetworkCommissioning::JOIN_NETWORK, &payload.into_vec(), "Join Network status",
+            JOIN_NETWORK_TIMEOUT, "Network Status after Join Network", NetworkState::JOINED)?;
+        match status.state {
+            NetworkState::JOINED => Ok(status),
+            _ => Err(MmbZigbeeModemError::new("Join Network: Couldn't join any network"))
+        }
+    }
+
+    pub fn leave_network(&mut self) -> Result<(), MmbZigbeeModemError> {
+        let status = self.commission(HeaderNetworkCommissioning::LEAVE_NETWORK, &[], "Leave Network status",
+            LEAVE_NETWORK_TIMEOUT, "Network Status after Leave Network", NetworkState::NOT_JOINED)?;
+        match status.state {
+            NetworkState::NOT_JOINED => Ok(()),
+            _ => Err(MmbZigbeeModemError::new("Leave Network: Couldn't leave the network"))
+        }
+    }
+
+    // Rejoins the network we were part of, e.g. after missing a network key update. An
+    // unsecured rejoin goes through the trust center again.
+    pub fn rejoin_network(&mut self, channel_mask: u32, secure: bool) -> Result<NetworkStatus, MmbZigbeeModemError> {
+        // [Secure: 1][Channel mask: 4]
+        let mut payload = PayloadWriter::new();
+        payload.u8(secure as u8).u32(channel_mask);
+
+        let status = self.commission(HeaderNetworkCommissioning::REJOIN_NETWORK, &payload.into_vec(), "Rejoin Network status",
+            JOIN_NETWORK_TIMEOUT, "Network Status after Rejoin Network", NetworkState::JOINED)?;
+        match status.state {
+            NetworkState::JOINED => Ok(status),
+            _ => Err(MmbZigbeeModemError::new("Rejoin Network: Couldn't rejoin the network"))
+        }
+    }
+
+    // Lets devices join through us for `duration` seconds. Every device that joins, rejoins or
+    // leaves in the meantime is reported as a TrustCenterDeviceUpdate event.
+    pub fn permit_join(&mut self, duration: u8) -> Result<(), MmbZigbeeModemError> {
+        self.network_commissioning_command(HeaderNetworkCommissioning::PERMIT_JOIN, &[duration], "Permit Join status")
+    }
+
+    // Closes the permit join window before it expires
+    pub fn close_permit_join(&mut self) -> Result<(), MmbZigbeeModemError> {
+        self.permit_join(0)
+    }
+
+    // Lets the module find and join a suitable network on its own, and rejoin it whenever it's
+    // lost. Returns as soon as the module accepts the command: the progress is reported with
+    // NetworkStatusChanged events and reflected in the modem state.
+    pub fn auto_join(&mut self, node_type: NodeType, channel_mask: u32, policy: JoinPolicy) -> Result<(), MmbZigbeeModemError> {
+        match node_type {
+            NodeType::ROUTER | NodeType::END_DEVICE | NodeType::SLEEPY_END_DEVICE => {},
+            _ => return Err(MmbZigbeeModemError::new("Network Auto Join: Only routers and end devices can join a network"))
+        }
+        // [Node type: 1][Channel mask: 4][Policy: 1][Extended PAN ID: 8]
+        let (policy, extended_pan_id) = match policy {
+            JoinPolicy::AnyNetwork => (0x00, 0),
+            JoinPolicy::ExtendedPanId(extended_pan_id) => (0x01, extended_pan_id)
+        };
+        let mut payload = PayloadWriter::new();
+        payload.u8(u8::from(node_type))
+            .u32(channel_mask)
+            .u8(policy)
+            .u64(extended_pan_id);
+
+        self.network_commissioning_command(HeaderNetworkCommissioning::NETWORK_AUTO_JOIN, &payload.into_vec(), "Network Auto Join status")?;
+        self.auto_join = true;
+        if self.state != MmbZigbeeModemState::ON_NETWORK {
+            self.set_state(MmbZigbeeModemState::JOINING_NETWORK);
+        }
+        Ok(())
+    }
+
+    // Stops the module from joining networks on its own and forgets the auto join settings.
+    // It doesn't leave the current network, if any.
+    pub fn reset_auto_join(&mut self) -> Result<(), MmbZigbeeModemError> {
+        self.network_commissioning_command(HeaderNetworkCommissioning::NETWORK_RESET_AUTO_JOIN, &[], "Network Reset Auto Join status")?;
+        self.auto_join = false;
+        if self.state == MmbZigbeeModemState::JOINING_NETWORK {
+            self.set_state(MmbZigbeeModemState::INITIALIZED);
+        }
+        Ok(())
+    }
+
+    pub fn network_status(&mut self) -> Result<NetworkStatus, MmbZigbeeModemError> {
+        let msg = self.transact(PrimaryHeader::NETWORK_COMMISSIONING_HEADER,
+            SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::NETWORK_STATUS_REQUEST),
+            SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::NETWORK_STATUS_RESPONSE),
+            &[], "Network Status Response")?;
+        let status = NetworkStatus::new(&msg.payload)?;
+        self.update_network_status(status);
+        Ok(status)
+    }
+
+    fn network_commissioning_command(&mut self, command: HeaderNetworkCommissioning, payload: &[u8], waiting_for: &'static str)
+        -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::NETWORK_COMMISSIONING_HEADER,
+            SecondaryHeader::HeaderNetworkCommissioning(command), payload, waiting_for)
+    }
+
+    // Sends a commissioning command and returns the Network Status that ends it, see
+    // wait_for_network_status(). The ones the module sends before the command's Status
+    // Response are kept, so none of them is missed.
+    fn commission(&mut self, command: HeaderNetworkCommissioning, payload: &[u8], waiting_for: &'static str,
+        timeout: Duration, status_waiting_for: &'static str, target: NetworkState) -> Result<NetworkStatus, MmbZigbeeModemError> {
+        self.network_status_log = Some(Vec::new());
+        let result = self.network_commissioning_command(command, payload, waiting_for);
+        let early_statuses = self.network_status_log.take().unwrap_or_default();
+        result?;
+        self.wait_for_network_status(timeout, status_waiting_for, target, early_statuses)
+    }
+
+    // Waits for the unsolicited Network Status frames the module sends while a commissioning
+    // command is in progress, until the network settles: either in `target`, or in any other
+    // state once the command is underway. The statuses received before the command's Status
+    // Response may be stale, so of those only `target` and the states that follow a JOINING
+    // (or LEAVING) one count.
+    fn wait_for_network_status(&mut self, timeout: Duration, waiting_for: &'static str, target: NetworkState,
+        early_statuses: Vec<NetworkStatus>) -> Result<NetworkStatus, MmbZigbeeModemError> {
+        let mut started = false;
+        for status in early_statuses {
+            match status.state {
+                state if state == target => return Ok(status),
+                state if is_in_progress(state) => started = true,
+                _ if started => return Ok(status),
+                _ => {}
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        let network_status_response = SecondaryHeader::HeaderNetworkCommissioning(HeaderNetworkCommissioning::NETWORK_STATUS_RESPONSE);
+        loop {
+            let now = Instant::now();
+            let remaining = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+            let msg = self.wait_for_frame(None, remaining, waiting_for,
+                |msg| msg.header.secondary_header == network_status_response)?;
+            let status = NetworkStatus::new(&msg.payload)?;
+            self.on_network_status(&msg).map_err(|_| MmbZigbeeModemError::new("Message format error: Bad Network Status"))?;
+            if status.state == target || !is_in_progress(status.state) {
+                return Ok(status);
+            }
+        }
+    }
+
+    pub(super) fn on_trust_center_device_update(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let update = DeviceUpdate::new(&msg.payload).map_err(|e| format!("{:?}", e))?;
+        trace!("Trust Center Device Update: {:?}", update);
+        self.events.push_back(MmbZigbeeModemEvent::TrustCenterDeviceUpdate(update));
+        Ok(())
+    }
+
+    // Network Status frames we didn't ask for mean the network changed under our feet
+    pub(super) fn on_network_status(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let status = NetworkStatus::new(&msg.payload).map_err(|e| format!("{:?}", e))?;
+        trace!("Network status: {:?}", status);
+        if let Some(ref mut log) = self.network_status_log {
+            log.push(status);
+        }
+        if self.network_status != Some(status) {
+            self.events.push_back(MmbZigbeeModemEvent::NetworkStatusChanged(status));
+        }
+        self.update_network_status(status);
+        Ok(())
+    }
+
+    fn update_network_status(&mut self, status: NetworkStatus) {
+        self.network_status = Some(status);
+        if !self.state.is_initialized() {
+            return;
+        }
+        let state = match status.state {
+            NetworkState::JOINED => MmbZigbeeModemState::ON_NETWORK,
+            NetworkState::JOINING | NetworkState::JOINED_NO_PARENT => MmbZigbeeModemState::JOINING_NETWORK,
+            // Auto join keeps looking for a network
+            _ if self.auto_join => MmbZigbeeModemState::JOINING_NETWORK,
+            _ => MmbZigbeeModemState::INITIALIZED
+        };
+        self.set_state(state);
+    }
+}
+
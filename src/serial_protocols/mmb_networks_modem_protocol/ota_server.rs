@@ -0,0 +1,361 @@
+use std::cmp;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    MmbZigbeeModemEvent, PrimaryHeader, SecondaryHeader, HeaderOtaBootload, PayloadReader, PayloadWriter};
+
+const OTA_FILE_EXTENSION: &str = "zigbee";
+const OTA_FILE_MAGIC: u32 = 0x0BEEF11E;
+// Everything up to the header string and total image size is mandatory
+const OTA_HEADER_MIN_SIZE: usize = 56;
+const OTA_HEADER_STRING_SIZE: usize = 32;
+
+// OTA header field control
+const SECURITY_CREDENTIAL_VERSION_PRESENT: u16 = 0x0001;
+const DEVICE_SPECIFIC_FILE: u16 = 0x0002;
+const HARDWARE_VERSIONS_PRESENT: u16 = 0x0004;
+
+// Query Next Image and Image Block Request field control
+const HARDWARE_VERSION_PRESENT: u8 = 0x01;
+const REQUEST_NODE_ADDRESS_PRESENT: u8 = 0x01;
+
+// The block has to fit in a single frame along with the rest of the Image Block Response
+const MAX_IMAGE_BLOCK_SIZE: u8 = 64;
+
+const OTA_STATUS_SUCCESS: u8 = 0x00;
+const OTA_STATUS_ABORT: u8 = 0x95;
+const OTA_STATUS_NO_IMAGE_AVAILABLE: u8 = 0x98;
+
+// A standard Zigbee OTA upgrade file, header included, as it is sent to the devices
+#[derive(Debug, Clone)]
+pub struct OtaImage {
+    pub manufacturer_code: u16,
+    pub image_type: u16,
+    pub file_version: u32,
+    pub header_string: String,
+    pub minimum_hardware_version: Option<u16>,
+    pub maximum_hardware_version: Option<u16>,
+    data: Vec<u8>
+}
+impl OtaImage {
+    // [Magic: 4][Header version: 2][Header length: 2][Field control: 2][Manufacturer code: 2][Image type: 2]
+    // [File version: 4][Stack version: 2][Header string: 32][Total image size: 4]
+    // ([Security credential version: 1])([Upgrade file destination: 8])([Minimum hardware version: 2][Maximum hardware version: 2])
+    pub fn new(data: Vec<u8>) -> Result<OtaImage, MmbZigbeeModemError> {
+        if data.len() < OTA_HEADER_MIN_SIZE {
+            return Err(MmbZigbeeModemError::new("OTA image: File too short"));
+        }
+        let (manufacturer_code, image_type, file_version, header_string, hardware_versions) = {
+            let mut reader = PayloadReader::new(&data);
+            if reader.u32()? != OTA_FILE_MAGIC {
+                return Err(MmbZigbeeModemError::new("OTA image: Wrong file identifier"));
+            }
+            let _header_version = reader.u16()?;
+            let header_length = reader.u16()?;
+            let field_control = reader.u16()?;
+            let manufacturer_code = reader.u16()?;
+            let image_type = reader.u16()?;
+            let file_version = reader.u32()?;
+            let _stack_version = reader.u16()?;
+            let header_string = String::from_utf8_lossy(&reader.bytes(OTA_HEADER_STRING_SIZE)?)
+                .trim_end_matches('\0').to_string();
+            let total_image_size = reader.u32()?;
+            if total_image_size as usize != data.len() || (header_length as usize) > data.len() {
+                return Err(MmbZigbeeModemError::new("OTA image: Size doesn't match the header"));
+            }
+            if field_control & SECURITY_CREDENTIAL_VERSION_PRESENT != 0 {
+                let _security_credential_version = reader.u8()?;
+            }
+            if field_control & DEVICE_SPECIFIC_FILE != 0 {
+                let _upgrade_file_destination = reader.u64()?;
+            }
+            let hardware_versions = if field_control & HARDWARE_VERSIONS_PRESENT != 0 {
+                Some((reader.u16()?, reader.u16()?))
+            } else {
+                None
+            };
+            (manufacturer_code, image_type, file_version, header_string, hardware_versions)
+        };
+        Ok(OtaImage {
+            manufacturer_code,
+            image_type,
+            file_version,
+            header_string,
+            minimum_hardware_version: hardware_versions.map(|(minimum, _)| minimum),
+            maximum_hardware_version: hardware_versions.map(|(_, maximum)| maximum),
+            data
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    fn supports_hardware_version(&self, hardware_version: Option<u16>) -> bool {
+        match hardware_version {
+            Some(version) => self.minimum_hardware_version.is_none_or(|minimum| version >= minimum) &&
+                self.maximum_hardware_version.is_none_or(|maximum| version <= maximum),
+            None => true
+        }
+    }
+}
+
+// Every OTA client request starts with [Source: 2][Source endpoint: 1][Transaction sequence number: 1]
+// and the responses start with the same fields, so the module can route them back.
+#[derive(Debug, Clone, Copy)]
+struct OtaClient {
+    short_address: u16,
+    endpoint: u8,
+    seq_number: u8
+}
+impl OtaClient {
+    fn read(reader: &mut PayloadReader) -> Result<OtaClient, MmbZigbeeModemError> {
+        Ok(OtaClient {
+            short_address: reader.u16()?,
+            endpoint: reader.u8()?,
+            seq_number: reader.u8()?
+        })
+    }
+
+    fn response(&self) -> PayloadWriter {
+        let mut payload = PayloadWriter::new();
+        payload.u16(self.short_address).u8(self.endpoint).u8(self.seq_number);
+        payload
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OtaUpgradeEnd {
+    pub short_address: u16,
+    pub status: u8,
+    pub manufacturer_code: u16,
+    pub image_type: u16,
+    pub file_version: u32
+}
+
+impl MmbZigbeeModemProtocol {
+    // Replaces the images we serve with the .zigbee files found in `directory`. Files that
+    // aren't valid OTA images are skipped. Returns how many images were loaded.
+    pub fn load_ota_images<P: AsRef<Path>>(&mut self, directory: P) -> Result<usize, MmbZigbeeModemError> {
+        let mut images = Vec::new();
+        for entry in fs::read_dir(directory).map_err(MmbZigbeeModemError::Io)? {
+            let path = entry.map_err(MmbZigbeeModemError::Io)?.path();
+            if path.extension().is_none_or(|extension| extension != OTA_FILE_EXTENSION) {
+                continue;
+            }
+            let mut data = Vec::new();
+            if let Err(e) = File::open(&path).and_then(|mut file| file.read_to_end(&mut data)) {
+                warn!("Couldn't read OTA image {}: {}", path.display(), e);
+                continue;
+            }
+            match OtaImage::new(data) {
+                Ok(image) => {
+                    trace!("Loaded OTA image {}: {:?}", path.display(), image);
+                    images.push(image);
+                },
+                Err(e) => warn!("Skipping OTA image {}: {:?}", path.display(), e)
+            }
+        }
+        self.ota_images = images;
+        Ok(self.ota_images.len())
+    }
+
+    pub fn ota_images(&self) -> &[OtaImage] {
+        &self.ota_images
+    }
+
+    fn find_ota_image(&self, manufacturer_code: u16, image_type: u16, file_version: u32) -> Option<&OtaImage> {
+        self.ota_images.iter().find(|image| image.manufacturer_code == manufacturer_code &&
+            image.image_type == image_type && image.file_version == file_version)
+    }
+
+    pub(super) fn on_ota_message(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let result = match msg.header.secondary_header {
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::QUERY_NEXT_IMAGE_REQUEST) => self.on_query_next_image(msg),
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::IMAGE_BLOCK_REQUEST) => self.on_image_block_request(msg),
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::UPGRADE_END_REQUEST) => self.on_upgrade_end(msg),
+            _ => return Err("Unknown OTA bootload message".to_string())
+        };
+        result.map_err(|e| format!("{:?}", e))
+    }
+
+    // [OtaClient][Field control: 1][Manufacturer code: 2][Image type: 2][Current file version: 4]([Hardware version: 2])
+    fn on_query_next_image(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(&msg.payload);
+        let client = OtaClient::read(&mut reader)?;
+        let field_control = reader.u8()?;
+        let manufacturer_code = reader.u16()?;
+        let image_type = reader.u16()?;
+        let current_file_version = reader.u32()?;
+        let hardware_version = if field_control & HARDWARE_VERSION_PRESENT != 0 {
+            Some(reader.u16()?)
+        } else {
+            None
+        };
+        trace!("Query Next Image from 0x{:04X}: manufacturer 0x{:04X}, type 0x{:04X}, version 0x{:08X}",
+            client.short_address, manufacturer_code, image_type, current_file_version);
+
+        // [OtaClient][Status: 1]([Manufacturer code: 2][Image type: 2][File version: 4][Image size: 4])
+        let mut response = client.response();
+        match self.ota_images.iter()
+            .filter(|image| image.manufacturer_code == manufacturer_code && image.image_type == image_type &&
+                image.file_version > current_file_version && image.supports_hardware_version(hardware_version))
+            .max_by_key(|image| image.file_version) {
+            Some(image) => {
+                response.u8(OTA_STATUS_SUCCESS)
+                    .u16(image.manufacturer_code)
+                    .u16(image.image_type)
+                    .u32(image.file_version)
+                    .u32(image.size());
+            },
+            None => {
+                response.u8(OTA_STATUS_NO_IMAGE_AVAILABLE);
+            }
+        }
+        self.send_command(PrimaryHeader::OTA_BOOTLOAD_HEADER,
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::QUERY_NEXT_IMAGE_RESPONSE), &response.into_vec())?;
+        Ok(())
+    }
+
+    // [OtaClient][Field control: 1][Manufacturer code: 2][Image type: 2][File version: 4][File offset: 4]
+    // [Maximum data size: 1]([Request node address: 8])
+    fn on_image_block_request(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(&msg.payload);
+        let client = OtaClient::read(&mut reader)?;
+        let field_control = reader.u8()?;
+        let manufacturer_code = reader.u16()?;
+        let image_type = reader.u16()?;
+        let file_version = reader.u32()?;
+        let file_offset = reader.u32()?;
+        let maximum_data_size = reader.u8()?;
+        if field_control & REQUEST_NODE_ADDRESS_PRESENT != 0 {
+            let _request_node_address = reader.u64()?;
+        }
+
+        // [OtaClient][Status: 1]([Manufacturer code: 2][Image type: 2][File version: 4][File offset: 4][Data size: 1][Data])
+        let mut response = client.response();
+        match self.find_ota_image(manufacturer_code, image_type, file_version) {
+            Some(image) if (file_offset as usize) < image.data.len() => {
+                let size = cmp::min(cmp::min(maximum_data_size, MAX_IMAGE_BLOCK_SIZE) as usize,
+                    image.data.len() - file_offset as usize);
+                let data = &image.data[file_offset as usize..file_offset as usize + size];
+                response.u8(OTA_STATUS_SUCCESS)
+                    .u16(manufacturer_code)
+                    .u16(image_type)
+                    .u32(file_version)
+                    .u32(file_offset)
+                    .u8(size as u8)
+                    .bytes(data);
+            },
+            Some(_) => {
+                response.u8(OTA_STATUS_ABORT);
+            },
+            None => {
+                response.u8(OTA_STATUS_NO_IMAGE_AVAILABLE);
+            }
+        }
+        self.send_command(PrimaryHeader::OTA_BOOTLOAD_HEADER,
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::IMAGE_BLOCK_RESPONSE), &response.into_vec())?;
+        Ok(())
+    }
+
+    // [OtaClient][Status: 1][Manufacturer code: 2][Image type: 2][File version: 4]
+    fn on_upgrade_end(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), MmbZigbeeModemError> {
+        let mut reader = PayloadReader::new(&msg.payload);
+        let client = OtaClient::read(&mut reader)?;
+        let upgrade_end = OtaUpgradeEnd {
+            short_address: client.short_address,
+            status: reader.u8()?,
+            manufacturer_code: reader.u16()?,
+            image_type: reader.u16()?,
+            file_version: reader.u32()?
+        };
+        trace!("Upgrade End: {:?}", upgrade_end);
+        self.events.push_back(MmbZigbeeModemEvent::OtaUpgradeEnded(upgrade_end));
+
+        // A client that failed to download or verify the image doesn't get a response
+        if upgrade_end.status != OTA_STATUS_SUCCESS {
+            return Ok(());
+        }
+        // [OtaClient][Manufacturer code: 2][Image type: 2][File version: 4][Current time: 4][Upgrade time: 4]
+        // Both times being 0 means upgrade now.
+        let mut response = client.response();
+        response.u16(upgrade_end.manufacturer_code)
+            .u16(upgrade_end.image_type)
+            .u32(upgrade_end.file_version)
+            .u32(0)
+            .u32(0);
+        self.send_command(PrimaryHeader::OTA_BOOTLOAD_HEADER,
+            SecondaryHeader::HeaderOtaBootload(HeaderOtaBootload::UPGRADE_END_RESPONSE), &response.into_vec())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An OTA file with the given optional header fields and `body` as its image data
+    fn ota_file(field_control: u16, optional_fields: &[u8], body: &[u8]) -> Vec<u8> {
+        let header_length = OTA_HEADER_MIN_SIZE + optional_fields.len();
+        let mut header_string = b"Test image".to_vec();
+        header_string.resize(OTA_HEADER_STRING_SIZE, 0);
+        let mut file = PayloadWriter::new();
+        file.bytes(&[0x1E, 0xF1, 0xEE, 0x0B])
+            .u16(0x0100)
+            .u16(header_length as u16)
+            .u16(field_control)
+            .u16(0x1234)
+            .u16(0x5678)
+            .u32(0x01020304)
+            .u16(0x0002)
+            .bytes(&header_string)
+            .u32((header_length + body.len()) as u32)
+            .bytes(optional_fields)
+            .bytes(body);
+        file.into_vec()
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let image = OtaImage::new(ota_file(0, &[], &[0xAA; 10])).unwrap();
+        assert_eq!(image.manufacturer_code, 0x1234);
+        assert_eq!(image.image_type, 0x5678);
+        assert_eq!(image.file_version, 0x01020304);
+        assert_eq!(image.header_string, "Test image");
+        assert_eq!(image.minimum_hardware_version, None);
+        assert_eq!(image.maximum_hardware_version, None);
+        assert_eq!(image.size() as usize, OTA_HEADER_MIN_SIZE + 10);
+        assert!(image.supports_hardware_version(Some(7)));
+    }
+
+    #[test]
+    fn parses_the_optional_header_fields() {
+        let mut optional_fields = vec![0x01];
+        optional_fields.extend_from_slice(&[0x77; 8]);
+        optional_fields.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        let field_control = SECURITY_CREDENTIAL_VERSION_PRESENT | DEVICE_SPECIFIC_FILE | HARDWARE_VERSIONS_PRESENT;
+        let image = OtaImage::new(ota_file(field_control, &optional_fields, &[])).unwrap();
+        assert_eq!(image.minimum_hardware_version, Some(2));
+        assert_eq!(image.maximum_hardware_version, Some(4));
+        assert!(!image.supports_hardware_version(Some(1)));
+        assert!(image.supports_hardware_version(Some(3)));
+        assert!(!image.supports_hardware_version(Some(5)));
+        assert!(image.supports_hardware_version(None));
+    }
+
+    #[test]
+    fn rejects_a_bad_magic_size_or_truncated_file() {
+        let mut file = ota_file(0, &[], &[0xAA; 10]);
+        file[0] = 0x1F;
+        assert!(OtaImage::new(file).is_err());
+
+        let mut file = ota_file(0, &[], &[0xAA; 10]);
+        file.push(0);
+        assert!(OtaImage::new(file).is_err());
+
+        let file = ota_file(0, &[], &[]);
+        assert!(OtaImage::new(file[..OTA_HEADER_MIN_SIZE - 1].to_vec()).is_err());
+    }
+}
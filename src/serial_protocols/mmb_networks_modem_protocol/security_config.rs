@@ -0,0 +1,126 @@
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, PrimaryHeader,
+    SecondaryHeader, HeaderSecurityConfig, PayloadReader, PayloadWriter};
+
+pub const KEY_SIZE: usize = 16;
+const INSTALL_CODE_CRC_SIZE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkKey {
+    pub key: [u8; KEY_SIZE],
+    pub sequence_number: u8
+}
+
+// Install codes are 6, 8, 12 or 16 bytes followed by their CRC-16/X-25, little endian.
+// The module derives the link key from them (AES-MMO hash), we only check they are sane.
+fn check_install_code(install_code: &[u8]) -> Result<(), MmbZigbeeModemError> {
+    if install_code.len() < INSTALL_CODE_CRC_SIZE {
+        return Err(MmbZigbeeModemError::new("Add Install Code: The install code is too short"));
+    }
+    let (code, crc) = install_code.split_at(install_code.len() - INSTALL_CODE_CRC_SIZE);
+    match code.len() {
+        6 | 8 | 12 | 16 => {},
+        _ => return Err(MmbZigbeeModemError::new("Add Install Code: Install codes are 6, 8, 12 or 16 bytes long"))
+    }
+    if crc[0] as u16 | (crc[1] as u16) << 8 != install_code_crc(code) {
+        return Err(MmbZigbeeModemError::new("Add Install Code: Wrong install code CRC"));
+    }
+    Ok(())
+}
+
+fn install_code_crc(code: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in code {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 0x0001 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl MmbZigbeeModemProtocol {
+    // Sets the trust center link key devices use to join (the well known ZigBeeAlliance09 key
+    // for Home Automation).
+    pub fn set_preconfigured_link_key(&mut self, key: &[u8; KEY_SIZE]) -> Result<(), MmbZigbeeModemError> {
+        self.security_config_command(HeaderSecurityConfig::SET_PRECONFIGURED_LINK_KEY, key, "Set Preconfigured Link Key status")
+    }
+
+    // Sets the network key used the next time we form a network
+    pub fn set_network_key(&mut self, network_key: &NetworkKey) -> Result<(), MmbZigbeeModemError> {
+        // [Key: 16][Key sequence number: 1]
+        let mut payload = PayloadWriter::new();
+        payload.bytes(&network_key.key).u8(network_key.sequence_number);
+        self.security_config_command(HeaderSecurityConfig::SET_NETWORK_KEY, &payload.into_vec(), "Set Network Key status")
+    }
+
+    pub fn network_key(&mut self) -> Result<NetworkKey, MmbZigbeeModemError> {
+        let msg = self.transact(PrimaryHeader::SECURITY_CONFIG_HEADER,
+            SecondaryHeader::HeaderSecurityConfig(HeaderSecurityConfig::NETWORK_KEY_REQUEST),
+            SecondaryHeader::HeaderSecurityConfig(HeaderSecurityConfig::NETWORK_KEY_RESPONSE),
+            &[], "Network Key Response")?;
+        // [Key: 16][Key sequence number: 1]
+        let mut reader = PayloadReader::new(&msg.payload);
+        let mut key = [0; KEY_SIZE];
+        key.copy_from_slice(&reader.bytes(KEY_SIZE)?);
+        Ok(NetworkKey {
+            key,
+            sequence_number: reader.u8()?
+        })
+    }
+
+    // Adds a link key derived from `install_code` (CRC included) for the device `eui64`
+    pub fn add_install_code(&mut self, eui64: u64, install_code: &[u8]) -> Result<(), MmbZigbeeModemError> {
+        check_install_code(install_code)?;
+        // [EUI64: 8][Install code length: 1][Install code + CRC]
+        let mut payload = PayloadWriter::new();
+        payload.u64(eui64).u8(install_code.len() as u8).bytes(install_code);
+        self.security_config_command(HeaderSecurityConfig::ADD_INSTALL_CODE, &payload.into_vec(), "Add Install Code status")
+    }
+
+    // When enabled the trust center only lets in devices with an install code derived link
+    // key, the preconfigured link key is refused.
+    pub fn set_install_code_only_joining(&mut self, enabled: bool) -> Result<(), MmbZigbeeModemError> {
+        self.security_config_command(HeaderSecurityConfig::INSTALL_CODE_ONLY_JOINING_WRITE, &[enabled as u8],
+            "Install Code Only Joining Write status")
+    }
+
+    fn security_config_command(&mut self, command: HeaderSecurityConfig, payload: &[u8], waiting_for: &'static str)
+        -> Result<(), MmbZigbeeModemError> {
+        self.execute(PrimaryHeader::SECURITY_CONFIG_HEADER, SecondaryHeader::HeaderSecurityConfig(command), payload, waiting_for)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The install code example from the ZigBee Base Device Behavior specification
+    const INSTALL_CODE: [u8; 18] = [0x83, 0xFE, 0xD3, 0x40, 0x7A, 0x93, 0x97, 0x23, 0xA5, 0xC6, 0x39, 0xB2,
+        0x69, 0x16, 0xD5, 0x05, 0xC3, 0xB5];
+
+    #[test]
+    fn install_code_crc_is_crc_16_x_25() {
+        assert_eq!(install_code_crc(b"123456789"), 0x906E);
+        assert_eq!(install_code_crc(&INSTALL_CODE[..16]), 0xB5C3);
+    }
+
+    #[test]
+    fn accepts_an_install_code_with_its_crc() {
+        assert!(check_install_code(&INSTALL_CODE).is_ok());
+
+        let mut install_code = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0, 0];
+        let crc = install_code_crc(&install_code[..6]);
+        install_code[6] = crc as u8;
+        install_code[7] = (crc >> 8) as u8;
+        assert!(check_install_code(&install_code).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_wrong_crc_or_length() {
+        let mut install_code = INSTALL_CODE;
+        install_code[17] ^= 0x01;
+        assert!(check_install_code(&install_code).is_err());
+        assert!(check_install_code(&INSTALL_CODE[2..]).is_err());
+        assert!(check_install_code(&[0xFF]).is_err());
+    }
+}
@@ -0,0 +1,615 @@
+use std::time::Duration;
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    MmbZigbeeModemEvent, PrimaryHeader, SecondaryHeader, HeaderZclMessages, PayloadReader, PayloadWriter};
+
+// Sleepy end devices only get their messages when they poll their parent
+const ZCL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const HOME_AUTOMATION_PROFILE_ID: u16 = 0x0104;
+
+// Address modes of the Send ZCL Message command
+const ADDRESS_MODE_GROUP: u8 = 0x01;
+const ADDRESS_MODE_UNICAST: u8 = 0x02;
+const ADDRESS_MODE_BROADCAST: u8 = 0x03;
+
+// ZCL frame control
+const FRAME_TYPE_CLUSTER_SPECIFIC: u8 = 0x01;
+const MANUFACTURER_SPECIFIC: u8 = 0x04;
+const DISABLE_DEFAULT_RESPONSE: u8 = 0x10;
+
+// ZCL global commands
+const READ_ATTRIBUTES: u8 = 0x00;
+const READ_ATTRIBUTES_RESPONSE: u8 = 0x01;
+const WRITE_ATTRIBUTES: u8 = 0x02;
+const WRITE_ATTRIBUTES_RESPONSE: u8 = 0x04;
+const CONFIGURE_REPORTING: u8 = 0x06;
+const CONFIGURE_REPORTING_RESPONSE: u8 = 0x07;
+const REPORT_ATTRIBUTES: u8 = 0x0A;
+const DEFAULT_RESPONSE: u8 = 0x0B;
+
+const ZCL_STATUS_SUCCESS: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Unicast{ short_address: u16, endpoint: u8 },
+    Group(u16),
+    // 0xFFFF every device, 0xFFFD devices with the receiver on when idle, 0xFFFC routers
+    Broadcast{ address: u16, endpoint: u8 }
+}
+impl Destination {
+    // [Address mode: 1][Address: 2][Endpoint: 1]
+    pub(super) fn write(&self, payload: &mut PayloadWriter) {
+        match *self {
+            Destination::Unicast{ short_address, endpoint } => payload.u8(ADDRESS_MODE_UNICAST).u16(short_address).u8(endpoint),
+            Destination::Group(group_id) => payload.u8(ADDRESS_MODE_GROUP).u16(group_id).u8(0xFF),
+            Destination::Broadcast{ address, endpoint } => payload.u8(ADDRESS_MODE_BROADCAST).u16(address).u8(endpoint)
+        };
+    }
+}
+
+// Where a ZCL message we received comes from:
+// [Source: 2][Source endpoint: 1][Destination endpoint: 1][Profile ID: 2][Cluster ID: 2]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZclSource {
+    pub short_address: u16,
+    pub endpoint: u8,
+    pub destination_endpoint: u8,
+    pub profile_id: u16,
+    pub cluster_id: u16
+}
+
+// [Frame control: 1]([Manufacturer code: 2])[Transaction sequence number: 1][Command ID: 1][Payload]
+#[derive(Debug, Clone)]
+pub(super) struct ZclFrame {
+    pub frame_control: u8,
+    pub manufacturer_code: Option<u16>,
+    pub seq_number: u8,
+    pub command_id: u8,
+    pub payload: Vec<u8>
+}
+impl ZclFrame {
+    fn read(reader: &mut PayloadReader) -> Result<ZclFrame, MmbZigbeeModemError> {
+        let frame_control = reader.u8()?;
+        let manufacturer_code = if frame_control & MANUFACTURER_SPECIFIC != 0 {
+            Some(reader.u16()?)
+        } else {
+            None
+        };
+        Ok(ZclFrame {
+            frame_control,
+            manufacturer_code,
+            seq_number: reader.u8()?,
+            command_id: reader.u8()?,
+            payload: reader.remaining().to_vec()
+        })
+    }
+
+    fn write(&self, payload: &mut PayloadWriter) {
+        payload.u8(self.frame_control);
+        if let Some(manufacturer_code) = self.manufacturer_code {
+            payload.u16(manufacturer_code);
+        }
+        payload.u8(self.seq_number).u8(self.command_id).bytes(&self.payload);
+    }
+
+    pub fn is_cluster_specific(&self) -> bool {
+        self.frame_control & 0x03 == FRAME_TYPE_CLUSTER_SPECIFIC
+    }
+
+    // A Default Response carries the ID of the command it answers: [Command ID: 1][Status: 1]
+    fn is_default_response_to(&self, command_id: u8) -> bool {
+        !self.is_cluster_specific() && self.command_id == DEFAULT_RESPONSE && self.payload.first() == Some(&command_id)
+    }
+}
+
+// A ZCL message received by the host: [ZclSource][ZclFrame]
+pub(super) fn read_zcl_message(payload: &[u8]) -> Result<(ZclSource, ZclFrame), MmbZigbeeModemError> {
+    let mut reader = PayloadReader::new(payload);
+    let source = ZclSource {
+        short_address: reader.u16()?,
+        endpoint: reader.u8()?,
+        destination_endpoint: reader.u8()?,
+        profile_id: reader.u16()?,
+        cluster_id: reader.u16()?
+    };
+    Ok((source, ZclFrame::read(&mut reader)?))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    Boolean(bool),
+    Bitmap8(u8),
+    Bitmap16(u16),
+    Bitmap32(u32),
+    Uint8(u8),
+    Uint16(u16),
+    Uint24(u32),
+    Uint32(u32),
+    Uint48(u64),
+    Int8(i8),
+    Int16(i16),
+    Int24(i32),
+    Int32(i32),
+    Enum8(u8),
+    Enum16(u16),
+    Float(f32),
+    OctetString(Vec<u8>),
+    CharacterString(String),
+    UtcTime(u32),
+    ClusterId(u16),
+    AttributeId(u16),
+    IeeeAddress(u64)
+}
+impl AttributeValue {
+    // ZCL data type identifiers
+    pub fn data_type(&self) -> u8 {
+        match *self {
+            AttributeValue::Boolean(_) => 0x10,
+            AttributeValue::Bitmap8(_) => 0x18,
+            AttributeValue::Bitmap16(_) => 0x19,
+            AttributeValue::Bitmap32(_) => 0x1B,
+            AttributeValue::Uint8(_) => 0x20,
+            AttributeValue::Uint16(_) => 0x21,
+            AttributeValue::Uint24(_) => 0x22,
+            AttributeValue::Uint32(_) => 0x23,
+            AttributeValue::Uint48(_) => 0x25,
+            AttributeValue::Int8(_) => 0x28,
+            AttributeValue::Int16(_) => 0x29,
+            AttributeValue::Int24(_) => 0x2A,
+            AttributeValue::Int32(_) => 0x2B,
+            AttributeValue::Enum8(_) => 0x30,
+            AttributeValue::Enum16(_) => 0x31,
+            AttributeValue::Float(_) => 0x39,
+            AttributeValue::OctetString(_) => 0x41,
+            AttributeValue::CharacterString(_) => 0x42,
+            AttributeValue::UtcTime(_) => 0xE2,
+            AttributeValue::ClusterId(_) => 0xE8,
+            AttributeValue::AttributeId(_) => 0xE9,
+            AttributeValue::IeeeAddress(_) => 0xF0
+        }
+    }
+
+    // We can't skip a value of a type we don't know, so the rest of the records are lost
+    pub(super) fn read(data_type: u8, reader: &mut PayloadReader) -> Result<AttributeValue, MmbZigbeeModemError> {
+        Ok(match data_type {
+            0x10 => AttributeValue::Boolean(reader.u8()? != 0),
+            0x18 => AttributeValue::Bitmap8(reader.u8()?),
+            0x19 => AttributeValue::Bitmap16(reader.u16()?),
+            0x1B => AttributeValue::Bitmap32(reader.u32()?),
+            0x20 => AttributeValue::Uint8(reader.u8()?),
+            0x21 => AttributeValue::Uint16(reader.u16()?),
+            0x22 => AttributeValue::Uint24(reader.uint(3)? as u32),
+            0x23 => AttributeValue::Uint32(reader.u32()?),
+            0x25 => AttributeValue::Uint48(reader.uint(6)?),
+            0x28 => AttributeValue::Int8(reader.i8()?),
+            0x29 => AttributeValue::Int16(reader.u16()? as i16),
+            0x2A => AttributeValue::Int24(reader.int(3)? as i32),
+            0x2B => AttributeValue::Int32(reader.u32()? as i32),
+            0x30 => AttributeValue::Enum8(reader.u8()?),
+            0x31 => AttributeValue::Enum16(reader.u16()?),
+            0x39 => AttributeValue::Float(f32::from_bits(reader.u32()?)),
+            0x41 => {
+                let len = reader.u8()?;
+                AttributeValue::OctetString(reader.bytes(len as usize)?)
+            },
+            0x42 => {
+                let len = reader.u8()?;
+                AttributeValue::CharacterString(String::from_utf8_lossy(&reader.bytes(len as usize)?).into_owned())
+            },
+            0xE2 => AttributeValue::UtcTime(reader.u32()?),
+            0xE8 => AttributeValue::ClusterId(reader.u16()?),
+            0xE9 => AttributeValue::AttributeId(reader.u16()?),
+            0xF0 => AttributeValue::IeeeAddress(reader.u64()?),
+            _ => return Err(MmbZigbeeModemError::new("ZCL: Unsupported attribute data type"))
+        })
+    }
+
+    pub(super) fn write(&self, payload: &mut PayloadWriter) -> Result<(), MmbZigbeeModemError> {
+        match *self {
+            AttributeValue::Boolean(value) => payload.u8(value as u8),
+            AttributeValue::Bitmap8(value) | AttributeValue::Uint8(value) | AttributeValue::Enum8(value) => payload.u8(value),
+            AttributeValue::Bitmap16(value) | AttributeValue::Uint16(value) | AttributeValue::Enum16(value) |
+            AttributeValue::ClusterId(value) | AttributeValue::AttributeId(value) => payload.u16(value),
+            AttributeValue::Bitmap32(value) | AttributeValue::Uint32(value) | AttributeValue::UtcTime(value) => payload.u32(value),
+            AttributeValue::Uint24(value) => payload.uint(value as u64 & 0xFFFFFF, 3),
+            AttributeValue::Uint48(value) => payload.uint(value & 0xFFFFFFFFFFFF, 6),
+            AttributeValue::Int8(value) => payload.i8(value),
+            AttributeValue::Int16(value) => payload.u16(value as u16),
+            AttributeValue::Int24(value) => payload.uint(value as u64 & 0xFFFFFF, 3),
+            AttributeValue::Int32(value) => payload.u32(value as u32),
+            AttributeValue::Float(value) => payload.u32(value.to_bits()),
+            AttributeValue::OctetString(ref value) => {
+                if value.len() > 0xFE {
+                    return Err(MmbZigbeeModemError::new("ZCL: Octet string too long"));
+                }
+                payload.u8(value.len() as u8).bytes(value)
+            },
+            AttributeValue::CharacterString(ref value) => {
+                if value.len() > 0xFE {
+                    return Err(MmbZigbeeModemError::new("ZCL: Character string too long"));
+                }
+                payload.u8(value.len() as u8).bytes(value.as_bytes())
+            },
+            AttributeValue::IeeeAddress(value) => payload.u64(value)
+        };
+        Ok(())
+    }
+}
+
+// Attribute values are kept (and reported) per node, endpoint, cluster and attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AttributeKey {
+    pub short_address: u16,
+    pub endpoint: u8,
+    pub cluster_id: u16,
+    pub attribute_id: u16
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadAttributeRecord {
+    pub attribute_id: u16,
+    pub status: u8,
+    // Only there when the status is SUCCESS
+    pub value: Option<AttributeValue>
+}
+
+// Attributes that couldn't be written or configured, with the reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeStatusRecord {
+    pub attribute_id: u16,
+    pub status: u8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportingConfiguration {
+    pub attribute_id: u16,
+    pub data_type: u8,
+    // Seconds
+    pub minimum_interval: u16,
+    pub maximum_interval: u16,
+    // Only for analog data types (integers, floats, time), same type as the attribute
+    pub reportable_change: Option<AttributeValue>
+}
+impl ReportingConfiguration {
+    // [Direction: 1][Attribute ID: 2][Data type: 1][Minimum interval: 2][Maximum interval: 2]([Reportable change])
+    // The reportable change is there if and only if the data type is analog, so a mismatch
+    // would make the rest of the records unreadable.
+    fn write(&self, payload: &mut PayloadWriter) -> Result<(), MmbZigbeeModemError> {
+        match self.reportable_change {
+            Some(ref change) if !is_analog_data_type(self.data_type) || change.data_type() != self.data_type =>
+                return Err(MmbZigbeeModemError::new("ZCL: The reportable change doesn't match the attribute data type")),
+            None if is_analog_data_type(self.data_type) =>
+                return Err(MmbZigbeeModemError::new("ZCL: Analog attributes need a reportable change")),
+            _ => {}
+        }
+        payload.u8(0x00)
+            .u16(self.attribute_id)
+            .u8(self.data_type)
+            .u16(self.minimum_interval)
+            .u16(self.maximum_interval);
+        if let Some(ref reportable_change) = self.reportable_change {
+            reportable_change.write(payload)?;
+        }
+        Ok(())
+    }
+}
+
+// Integers, floats and time
+fn is_analog_data_type(data_type: u8) -> bool {
+    matches!(data_type, 0x20..=0x2F | 0x38..=0x3A | 0xE0..=0xE2)
+}
+
+// Write Attributes and Configure Reporting Responses are a single SUCCESS status when
+// everything went fine, or one record per failed attribute.
+fn read_attribute_status_records(payload: &[u8], with_direction: bool) -> Result<Vec<AttributeStatusRecord>, MmbZigbeeModemError> {
+    if payload.len() == 1 && payload[0] == ZCL_STATUS_SUCCESS {
+        return Ok(Vec::new());
+    }
+    let mut reader = PayloadReader::new(payload);
+    let mut records = Vec::new();
+    while !reader.remaining().is_empty() {
+        let status = reader.u8()?;
+        if with_direction {
+            let _direction = reader.u8()?;
+        }
+        records.push(AttributeStatusRecord {
+            attribute_id: reader.u16()?,
+            status
+        });
+    }
+    Ok(records.into_iter().filter(|record| record.status != ZCL_STATUS_SUCCESS).collect())
+}
+
+impl MmbZigbeeModemProtocol {
+    // Endpoint and profile the host sends its ZCL messages from
+    pub fn set_zcl_source(&mut self, endpoint: u8, profile_id: u16) {
+        self.zcl_source_endpoint = endpoint;
+        self.zcl_profile_id = profile_id;
+    }
+
+    // Last value we got for an attribute, from a report or a read
+    pub fn attribute_value(&self, key: &AttributeKey) -> Option<&AttributeValue> {
+        self.attribute_values.get(key)
+    }
+
+    pub fn read_attributes(&mut self, short_address: u16, endpoint: u8, cluster_id: u16, attribute_ids: &[u16])
+        -> Result<Vec<ReadAttributeRecord>, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        for &attribute_id in attribute_ids {
+            request.u16(attribute_id);
+        }
+        let response = self.zcl_global_request(short_address, endpoint, cluster_id, READ_ATTRIBUTES,
+            READ_ATTRIBUTES_RESPONSE, &request.into_vec())?;
+
+        // [Attribute ID: 2][Status: 1]([Data type: 1][Value])
+        let mut reader = PayloadReader::new(&response);
+        let mut records = Vec::new();
+        while !reader.remaining().is_empty() {
+            let attribute_id = reader.u16()?;
+            let status = reader.u8()?;
+            let value = if status == ZCL_STATUS_SUCCESS {
+                let data_type = reader.u8()?;
+                let value = AttributeValue::read(data_type, &mut reader)?;
+                self.attribute_values.insert(AttributeKey {
+                    short_address,
+                    endpoint,
+                    cluster_id,
+                    attribute_id
+                }, value.clone());
+                Some(value)
+            } else {
+                None
+            };
+            records.push(ReadAttributeRecord {
+                attribute_id,
+                status,
+                value
+            });
+        }
+        Ok(records)
+    }
+
+    // Returns the attributes that couldn't be written, so an empty list means success
+    pub fn write_attributes(&mut self, short_address: u16, endpoint: u8, cluster_id: u16, attributes: &[(u16, AttributeValue)])
+        -> Result<Vec<AttributeStatusRecord>, MmbZigbeeModemError> {
+        // [Attribute ID: 2][Data type: 1][Value]
+        let mut request = PayloadWriter::new();
+        for &(attribute_id, ref value) in attributes {
+            request.u16(attribute_id).u8(value.data_type());
+            value.write(&mut request)?;
+        }
+        let response = self.zcl_global_request(short_address, endpoint, cluster_id, WRITE_ATTRIBUTES,
+            WRITE_ATTRIBUTES_RESPONSE, &request.into_vec())?;
+        read_attribute_status_records(&response, false)
+    }
+
+    // Returns the attributes whose reporting couldn't be configured, so an empty list means success
+    pub fn configure_reporting(&mut self, short_address: u16, endpoint: u8, cluster_id: u16, configurations: &[ReportingConfiguration])
+        -> Result<Vec<AttributeStatusRecord>, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        for configuration in configurations {
+            configuration.write(&mut request)?;
+        }
+        let response = self.zcl_global_request(short_address, endpoint, cluster_id, CONFIGURE_REPORTING,
+            CONFIGURE_REPORTING_RESPONSE, &request.into_vec())?;
+        read_attribute_status_records(&response, true)
+    }
+
+    // Sends a ZCL frame from the host endpoint. Returns the transaction sequence number used.
+    pub(super) fn send_zcl_frame(&mut self, destination: Destination, cluster_id: u16, cluster_specific: bool, command_id: u8,
+        payload: &[u8]) -> Result<u8, MmbZigbeeModemError> {
+        let seq_number = self.zcl_seq_number;
+        self.zcl_seq_number = self.zcl_seq_number.wrapping_add(1);
+
+        let mut frame_control = 0;
+        if cluster_specific {
+            frame_control |= FRAME_TYPE_CLUSTER_SPECIFIC;
+        }
+        // Nobody would be listening for the Default Responses of a group or broadcast
+        if let Destination::Unicast{ .. } = destination {} else {
+            frame_control |= DISABLE_DEFAULT_RESPONSE;
+        }
+        let frame = ZclFrame {
+            frame_control,
+            manufacturer_code: None,
+            seq_number,
+            command_id,
+            payload: payload.to_vec()
+        };
+
+        // [Address mode: 1][Destination: 2][Destination endpoint: 1][Source endpoint: 1]
+        // [Profile ID: 2][Cluster ID: 2][ZCL frame]
+        let mut message = PayloadWriter::new();
+        destination.write(&mut message);
+        message.u8(self.zcl_source_endpoint).u16(self.zcl_profile_id).u16(cluster_id);
+        frame.write(&mut message);
+        self.execute(PrimaryHeader::ZCL_MESSAGES_HEADER, SecondaryHeader::HeaderZclMessages(HeaderZclMessages::SEND_ZCL_MESSAGE),
+            &message.into_vec(), "Send ZCL Message status")?;
+        Ok(seq_number)
+    }
+
+    // Waits for the answer to the ZCL command `command_id` (frame `seq_number`) we sent to
+    // `short_address`: either `response_command_id` or a Default Response to the command. A
+    // successful Default Response carries no frame to return (None), a failed one is an error.
+    pub(super) fn wait_for_zcl_response(&mut self, short_address: u16, cluster_id: u16, seq_number: u8, command_id: u8,
+        response_command_id: u8) -> Result<Option<ZclFrame>, MmbZigbeeModemError> {
+        let zcl_message_received = SecondaryHeader::HeaderZclMessages(HeaderZclMessages::ZCL_MESSAGE_RECEIVED);
+        let msg = self.wait_for_frame(None, ZCL_RESPONSE_TIMEOUT, "ZCL response", |msg| {
+            if msg.header.secondary_header != zcl_message_received {
+                return false;
+            }
+            match read_zcl_message(&msg.payload) {
+                Ok((source, frame)) => source.short_address == short_address && source.cluster_id == cluster_id &&
+                    frame.seq_number == seq_number &&
+                    (frame.command_id == response_command_id || frame.is_default_response_to(command_id)),
+                Err(_) => false
+            }
+        })?;
+        let (_, frame) = read_zcl_message(&msg.payload)?;
+        if frame.is_default_response_to(command_id) {
+            let mut reader = PayloadReader::new(&frame.payload);
+            let _answered_command_id = reader.u8()?;
+            let status = reader.u8()?;
+            if status != ZCL_STATUS_SUCCESS {
+                return Err(MmbZigbeeModemError::ZclFailed{ cluster_id, command_id, status });
+            }
+            return Ok(None);
+        }
+        Ok(Some(frame))
+    }
+
+    fn zcl_global_request(&mut self, short_address: u16, endpoint: u8, cluster_id: u16, command_id: u8, response_command_id: u8,
+        payload: &[u8]) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let destination = Destination::Unicast{ short_address, endpoint };
+        let seq_number = self.send_zcl_frame(destination, cluster_id, false, command_id, payload)?;
+        // A successful Default Response has no records, like a response where every attribute succeeded
+        let frame = self.wait_for_zcl_response(short_address, cluster_id, seq_number, command_id, response_command_id)?;
+        Ok(frame.map(|frame| frame.payload).unwrap_or_default())
+    }
+
+    pub(super) fn on_zcl_message(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        let (source, frame) = read_zcl_message(&msg.payload).map_err(|e| format!("{:?}", e))?;
+        if !frame.is_cluster_specific() && frame.command_id == REPORT_ATTRIBUTES {
+            return self.on_attribute_report(&source, &frame).map_err(|e| format!("{:?}", e));
+        }
+        trace!("Unhandled ZCL message from {:?}: {:?}", source, frame);
+        Ok(())
+    }
+
+    fn on_attribute_report(&mut self, source: &ZclSource, frame: &ZclFrame) -> Result<(), MmbZigbeeModemError> {
+        // [Attribute ID: 2][Data type: 1][Value]
+        let mut reader = PayloadReader::new(&frame.payload);
+        while !reader.remaining().is_empty() {
+            let key = AttributeKey {
+                short_address: source.short_address,
+                endpoint: source.endpoint,
+                cluster_id: source.cluster_id,
+                attribute_id: reader.u16()?
+            };
+            let data_type = reader.u8()?;
+            let value = AttributeValue::read(data_type, &mut reader)?;
+            trace!("Attribute report: {:?} = {:?}", key, value);
+            self.attribute_values.insert(key, value.clone());
+            self.events.push_back(MmbZigbeeModemEvent::AttributeReported(key, value));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_response(command_id: u8, status: u8) -> ZclFrame {
+        ZclFrame {
+            frame_control: DISABLE_DEFAULT_RESPONSE,
+            manufacturer_code: None,
+            seq_number: 1,
+            command_id: DEFAULT_RESPONSE,
+            payload: vec![command_id, status]
+        }
+    }
+
+    #[test]
+    fn default_responses_carry_the_request_command_id() {
+        assert!(default_response(CONFIGURE_REPORTING, ZCL_STATUS_SUCCESS).is_default_response_to(CONFIGURE_REPORTING));
+        assert!(!default_response(CONFIGURE_REPORTING, ZCL_STATUS_SUCCESS).is_default_response_to(CONFIGURE_REPORTING_RESPONSE));
+        assert!(!default_response(READ_ATTRIBUTES, ZCL_STATUS_SUCCESS).is_default_response_to(CONFIGURE_REPORTING));
+    }
+
+    fn encode(value: &AttributeValue) -> Vec<u8> {
+        let mut payload = PayloadWriter::new();
+        value.write(&mut payload).unwrap();
+        payload.into_vec()
+    }
+
+    fn decode(data_type: u8, bytes: &[u8]) -> AttributeValue {
+        let mut reader = PayloadReader::new(bytes);
+        let value = AttributeValue::read(data_type, &mut reader).unwrap();
+        assert!(reader.remaining().is_empty());
+        value
+    }
+
+    #[test]
+    fn encodes_known_values() {
+        assert_eq!(encode(&AttributeValue::Uint16(0x1234)), [0x34, 0x12]);
+        assert_eq!(encode(&AttributeValue::Uint24(0x123456)), [0x56, 0x34, 0x12]);
+        assert_eq!(encode(&AttributeValue::Uint48(0x123456789ABC)), [0xBC, 0x9A, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(encode(&AttributeValue::Int24(-2)), [0xFE, 0xFF, 0xFF]);
+        assert_eq!(encode(&AttributeValue::CharacterString("HA".to_string())), [0x02, b'H', b'A']);
+    }
+
+    #[test]
+    fn decodes_known_values() {
+        assert_eq!(decode(0x22, &[0x56, 0x34, 0x12]), AttributeValue::Uint24(0x123456));
+        assert_eq!(decode(0x25, &[0xBC, 0x9A, 0x78, 0x56, 0x34, 0x12]), AttributeValue::Uint48(0x123456789ABC));
+        assert_eq!(decode(0x2A, &[0xFE, 0xFF, 0xFF]), AttributeValue::Int24(-2));
+        assert_eq!(decode(0x2A, &[0xFF, 0xFF, 0x7F]), AttributeValue::Int24(0x7FFFFF));
+        assert_eq!(decode(0x2A, &[0x00, 0x00, 0x80]), AttributeValue::Int24(-0x800000));
+    }
+
+    #[test]
+    fn encoded_values_decode_back() {
+        let values = [
+            AttributeValue::Boolean(true),
+            AttributeValue::Bitmap32(0xDEADBEEF),
+            AttributeValue::Uint24(0xFFFFFF),
+            AttributeValue::Uint48(0xFFFFFFFFFFFF),
+            AttributeValue::Int16(-300),
+            AttributeValue::Int24(-0x800000),
+            AttributeValue::Int24(0x7FFFFF),
+            AttributeValue::Int32(-1),
+            AttributeValue::Float(21.5),
+            AttributeValue::OctetString(vec![1, 2, 3]),
+            AttributeValue::UtcTime(0x12345678),
+            AttributeValue::IeeeAddress(0x0011223344556677)
+        ];
+        for value in &values {
+            assert_eq!(&decode(value.data_type(), &encode(value)), value);
+        }
+    }
+
+    #[test]
+    fn out_of_range_24_and_48_bit_values_are_truncated() {
+        assert_eq!(encode(&AttributeValue::Uint24(0x01123456)), [0x56, 0x34, 0x12]);
+        assert_eq!(encode(&AttributeValue::Uint48(0x01_123456789ABC)), [0xBC, 0x9A, 0x78, 0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn rejects_truncated_values_and_unknown_types() {
+        assert!(AttributeValue::read(0x25, &mut PayloadReader::new(&[0x00, 0x01, 0x02])).is_err());
+        assert!(AttributeValue::read(0x42, &mut PayloadReader::new(&[0x05, b'a'])).is_err());
+        assert!(AttributeValue::read(0xFF, &mut PayloadReader::new(&[0x00])).is_err());
+    }
+
+    fn reporting(data_type: u8, reportable_change: Option<AttributeValue>) -> ReportingConfiguration {
+        ReportingConfiguration {
+            attribute_id: 0x0000,
+            data_type,
+            minimum_interval: 1,
+            maximum_interval: 300,
+            reportable_change
+        }
+    }
+
+    fn encode_reporting(configuration: &ReportingConfiguration) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let mut payload = PayloadWriter::new();
+        configuration.write(&mut payload)?;
+        Ok(payload.into_vec())
+    }
+
+    #[test]
+    fn encodes_reporting_configurations() {
+        assert_eq!(encode_reporting(&reporting(0x29, Some(AttributeValue::Int16(50)))).unwrap(),
+            [0x00, 0x00, 0x00, 0x29, 0x01, 0x00, 0x2C, 0x01, 0x32, 0x00]);
+        assert_eq!(encode_reporting(&reporting(0x10, None)).unwrap(), [0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x2C, 0x01]);
+    }
+
+    #[test]
+    fn rejects_a_reportable_change_not_matching_the_data_type() {
+        assert!(encode_reporting(&reporting(0x29, None)).is_err());
+        assert!(encode_reporting(&reporting(0x29, Some(AttributeValue::Uint16(50)))).is_err());
+        assert!(encode_reporting(&reporting(0x10, Some(AttributeValue::Boolean(true)))).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::Duration;
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, MmbZigbeeModemMessage,
+    PrimaryHeader, SecondaryHeader, HeaderZdoMessages, PayloadReader, PayloadWriter, NodeType, SimpleDescriptor};
+
+// Sleepy end devices only get their messages when they poll their parent
+const ZDO_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Broadcast to every device with the receiver on when idle
+const BROADCAST_RX_ON_WHEN_IDLE: u16 = 0xFFFD;
+// Anything from 0xFFF8 up is a broadcast address
+const BROADCAST_ADDRESSES: u16 = 0xFFF8;
+
+const NWK_ADDR_REQ: u16 = 0x0000;
+const IEEE_ADDR_REQ: u16 = 0x0001;
+const NODE_DESC_REQ: u16 = 0x0002;
+const POWER_DESC_REQ: u16 = 0x0003;
+const SIMPLE_DESC_REQ: u16 = 0x0004;
+const ACTIVE_EP_REQ: u16 = 0x0005;
+const MATCH_DESC_REQ: u16 = 0x0006;
+// Responses use the cluster of their request with the top bit set
+const ZDO_RESPONSE: u16 = 0x8000;
+
+const ZDO_STATUS_SUCCESS: u8 = 0x00;
+
+// NWK_addr_rsp and IEEE_addr_rsp. The associated devices are only there for extended requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressResponse {
+    pub eui64: u64,
+    pub short_address: u16,
+    pub associated_devices: Vec<u16>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeDescriptor {
+    pub logical_type: NodeType,
+    pub complex_descriptor_available: bool,
+    pub user_descriptor_available: bool,
+    pub frequency_band: u8,
+    pub mac_capabilities: u8,
+    pub manufacturer_code: u16,
+    pub maximum_buffer_size: u8,
+    pub maximum_incoming_transfer_size: u16,
+    pub server_mask: u16,
+    pub maximum_outgoing_transfer_size: u16,
+    pub descriptor_capabilities: u8
+}
+
+// Each field is a 4 bit value/bitmap, as in the ZDO power descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerDescriptor {
+    pub current_power_mode: u8,
+    pub available_power_sources: u8,
+    pub current_power_source: u8,
+    pub current_power_source_level: u8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchDescriptorResponse {
+    pub short_address: u16,
+    pub endpoints: Vec<u8>
+}
+
+// [Source/destination: 2][Cluster ID: 2][ZDO sequence number: 1][ZDO payload]
+fn is_zdo_response(msg: &MmbZigbeeModemMessage, cluster_id: u16, zdo_seq_number: u8) -> bool {
+    if msg.header.secondary_header != SecondaryHeader::HeaderZdoMessages(HeaderZdoMessages::ZDO_RESPONSE_RECEIVED) {
+        return false;
+    }
+    let mut reader = PayloadReader::new(&msg.payload);
+    match (reader.u16(), reader.u16(), reader.u8()) {
+        (Ok(_), Ok(response_cluster_id), Ok(response_seq_number)) =>
+            response_cluster_id == cluster_id | ZDO_RESPONSE && response_seq_number == zdo_seq_number,
+        _ => false
+    }
+}
+
+fn read_address_response(payload: &[u8]) -> Result<AddressResponse, MmbZigbeeModemError> {
+    // [IEEE address: 8][NWK address: 2]([Associated device count: 1][Start index: 1][Associated devices: 2 * n])
+    let mut reader = PayloadReader::new(payload);
+    let eui64 = reader.u64()?;
+    let short_address = reader.u16()?;
+    let mut associated_devices = Vec::new();
+    if !reader.remaining().is_empty() {
+        let count = reader.u8()?;
+        let _start_index = reader.u8()?;
+        for _ in 0..count {
+            associated_devices.push(reader.u16()?);
+        }
+    }
+    Ok(AddressResponse {
+        eui64,
+        short_address,
+        associated_devices
+    })
+}
+
+impl MmbZigbeeModemProtocol {
+    // Looks up the short address of `eui64` with a broadcast NWK_addr_req
+    pub fn nwk_addr_request(&mut self, eui64: u64, extended: bool) -> Result<AddressResponse, MmbZigbeeModemError> {
+        // [IEEE address: 8][Request type: 1][Start index: 1]
+        let mut request = PayloadWriter::new();
+        request.u64(eui64).u8(extended as u8).u8(0);
+        let response = self.zdo_request(BROADCAST_RX_ON_WHEN_IDLE, NWK_ADDR_REQ, &request.into_vec())?;
+        read_address_response(&response)
+    }
+
+    pub fn ieee_addr_request(&mut self, short_address: u16, extended: bool) -> Result<AddressResponse, MmbZigbeeModemError> {
+        // [NWK address of interest: 2][Request type: 1][Start index: 1]
+        let mut request = PayloadWriter::new();
+        request.u16(short_address).u8(extended as u8).u8(0);
+        let response = self.zdo_request(short_address, IEEE_ADDR_REQ, &request.into_vec())?;
+        read_address_response(&response)
+    }
+
+    pub fn node_descriptor_request(&mut self, short_address: u16) -> Result<NodeDescriptor, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        request.u16(short_address);
+        let response = self.zdo_request(short_address, NODE_DESC_REQ, &request.into_vec())?;
+        // [NWK address of interest: 2][Node descriptor: 13]
+        let mut reader = PayloadReader::new(&response);
+        let _short_address = reader.u16()?;
+        let flags = reader.u8()?;
+        Ok(NodeDescriptor {
+            logical_type: NodeType::from(flags & 0x07),
+            complex_descriptor_available: flags & 0x08 != 0,
+            user_descriptor_available: flags & 0x10 != 0,
+            frequency_band: reader.u8()? >> 3,
+            mac_capabilities: reader.u8()?,
+            manufacturer_code: reader.u16()?,
+            maximum_buffer_size: reader.u8()?,
+            maximum_incoming_transfer_size: reader.u16()?,
+            server_mask: reader.u16()?,
+            maximum_outgoing_transfer_size: reader.u16()?,
+            descriptor_capabilities: reader.u8()?
+        })
+    }
+
+    pub fn power_descriptor_request(&mut self, short_address: u16) -> Result<PowerDescriptor, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        request.u16(short_address);
+        let response = self.zdo_request(short_address, POWER_DESC_REQ, &request.into_vec())?;
+        // [NWK address of interest: 2][Power descriptor: 2]
+        let mut reader = PayloadReader::new(&response);
+        let _short_address = reader.u16()?;
+        let low = reader.u8()?;
+        let high = reader.u8()?;
+        Ok(PowerDescriptor {
+            current_power_mode: low & 0x0F,
+            available_power_sources: low >> 4,
+            current_power_source: high & 0x0F,
+            current_power_source_level: high >> 4
+        })
+    }
+
+    pub fn active_endpoints_request(&mut self, short_address: u16) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        request.u16(short_address);
+        let response = self.zdo_request(short_address, ACTIVE_EP_REQ, &request.into_vec())?;
+        // [NWK address of interest: 2][Active endpoint count: 1][Endpoints: n]
+        let mut reader = PayloadReader::new(&response);
+        let _short_address = reader.u16()?;
+        let count = reader.u8()?;
+        reader.bytes(count as usize)
+    }
+
+    pub fn simple_descriptor_request(&mut self, short_address: u16, endpoint: u8) -> Result<SimpleDescriptor, MmbZigbeeModemError> {
+        let mut request = PayloadWriter::new();
+        request.u16(short_address).u8(endpoint);
+        let response = self.zdo_request(short_address, SIMPLE_DESC_REQ, &request.into_vec())?;
+        // [NWK address of interest: 2][Length: 1][Simple descriptor]
+        let mut reader = PayloadReader::new(&response);
+        let _short_address = reader.u16()?;
+        let _length = reader.u8()?;
+        SimpleDescriptor::read(&mut reader)
+    }
+
+    // Asks which endpoints of `short_address` match the profile and clusters. It can be a
+    // broadcast address, in which case the first device that answers wins.
+    pub fn match_descriptor_request(&mut self, short_address: u16, profile_id: u16, input_clusters: &[u16], output_clusters: &[u16])
+        -> Result<MatchDescriptorResponse, MmbZigbeeModemError> {
+        if input_clusters.len() > 0xFF || output_clusters.len() > 0xFF {
+            return Err(MmbZigbeeModemError::new("Match_Desc_req: Too many clusters"));
+        }
+        // [NWK address of interest: 2][Profile ID: 2][Input cluster count: 1][Input clusters: 2 * n]
+        // [Output cluster count: 1][Output clusters: 2 * n]
+        let mut request = PayloadWriter::new();
+        request.u16(short_address).u16(profile_id).u8(input_clusters.len() as u8);
+        for &cluster in input_clusters {
+            request.u16(cluster);
+        }
+        request.u8(output_clusters.len() as u8);
+        for &cluster in output_clusters {
+            request.u16(cluster);
+        }
+        let response = self.zdo_request(short_address, MATCH_DESC_REQ, &request.into_vec())?;
+        // [NWK address of interest: 2][Match length: 1][Endpoints: n]
+        let mut reader = PayloadReader::new(&response);
+        let short_address = reader.u16()?;
+        let count = reader.u8()?;
+        Ok(MatchDescriptorResponse {
+            short_address,
+            endpoints: reader.bytes(count as usize)?
+        })
+    }
+
+    // Sends a ZDO request and waits for the response with the same ZDO sequence number.
+    // Returns the response payload after its status.
+    fn zdo_request(&mut self, destination: u16, cluster_id: u16, request: &[u8]) -> Result<Vec<u8>, MmbZigbeeModemError> {
+        let zdo_seq_number = self.zdo_seq_number;
+        self.zdo_seq_number = self.zdo_seq_number.wrapping_add(1);
+
+        let command = if destination >= BROADCAST_ADDRESSES {
+            HeaderZdoMessages::ZDO_BROADCAST_REQUEST
+        } else {
+            HeaderZdoMessages::ZDO_UNICAST_REQUEST
+        };
+        let mut payload = PayloadWriter::new();
+        payload.u16(destination).u16(cluster_id).u8(zdo_seq_number).bytes(request);
+        self.execute(PrimaryHeader::ZDO_MESSAGES_HEADER, SecondaryHeader::HeaderZdoMessages(command),
+            &payload.into_vec(), "ZDO request status")?;
+
+        let msg = self.wait_for_frame(None, ZDO_RESPONSE_TIMEOUT, "ZDO response",
+            |msg| is_zdo_response(msg, cluster_id, zdo_seq_number))?;
+        // [Source: 2][Cluster ID: 2][ZDO sequence number: 1][Status: 1][Response]
+        let mut reader = PayloadReader::new(&msg.payload);
+        let _source = reader.u16()?;
+        let _cluster_id = reader.u16()?;
+        let _zdo_seq_number = reader.u8()?;
+        match reader.u8()? {
+            ZDO_STATUS_SUCCESS => Ok(reader.remaining().to_vec()),
+            status => Err(MmbZigbeeModemError::ZdoFailed{ cluster_id, status })
+        }
+    }
+
+    pub(super) fn on_zdo_message(&mut self, msg: &MmbZigbeeModemMessage) -> Result<(), String> {
+        // Responses nobody is waiting for anymore (late answers to broadcasts, timeouts...)
+        trace!("Unexpected ZDO message: {:?}", msg.payload);
+        Ok(())
+    }
+}
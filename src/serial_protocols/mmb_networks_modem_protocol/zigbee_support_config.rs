@@ -0,0 +1,97 @@
+use serial_protocols::mmb_networks_modem_protocol::{MmbZigbeeModemProtocol, MmbZigbeeModemError, PrimaryHeader,
+    SecondaryHeader, HeaderZigbeeSupportConfig, PayloadReader, PayloadWriter};
+
+// What an endpoint exposes, in the same layout ZDO uses on the air:
+// [Endpoint: 1][Profile ID: 2][Device ID: 2][Device version: 1]
+// [Input cluster count: 1][Input clusters: 2 * n][Output cluster count: 1][Output clusters: 2 * n]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimpleDescriptor {
+    pub endpoint: u8,
+    pub profile_id: u16,
+    pub device_id: u16,
+    pub device_version: u8,
+    pub input_clusters: Vec<u16>,
+    pub output_clusters: Vec<u16>
+}
+impl SimpleDescriptor {
+    pub(super) fn read(reader: &mut PayloadReader) -> Result<SimpleDescriptor, MmbZigbeeModemError> {
+        let endpoint = reader.u8()?;
+        let profile_id = reader.u16()?;
+        let device_id = reader.u16()?;
+        let device_version = reader.u8()? & 0x0F;
+        let input_count = reader.u8()?;
+        let mut input_clusters = Vec::with_capacity(input_count as usize);
+        for _ in 0..input_count {
+            input_clusters.push(reader.u16()?);
+        }
+        let output_count = reader.u8()?;
+        let mut output_clusters = Vec::with_capacity(output_count as usize);
+        for _ in 0..output_count {
+            output_clusters.push(reader.u16()?);
+        }
+        Ok(SimpleDescriptor {
+            endpoint,
+            profile_id,
+            device_id,
+            device_version,
+            input_clusters,
+            output_clusters
+        })
+    }
+
+    pub(super) fn write(&self, payload: &mut PayloadWriter) -> Result<(), MmbZigbeeModemError> {
+        if self.input_clusters.len() > 0xFF || self.output_clusters.len() > 0xFF {
+            return Err(MmbZigbeeModemError::new("Simple descriptor: Too many clusters"));
+        }
+        payload.u8(self.endpoint)
+            .u16(self.profile_id)
+            .u16(self.device_id)
+            .u8(self.device_version & 0x0F)
+            .u8(self.input_clusters.len() as u8);
+        for &cluster in &self.input_clusters {
+            payload.u16(cluster);
+        }
+        payload.u8(self.output_clusters.len() as u8);
+        for &cluster in &self.output_clusters {
+            payload.u16(cluster);
+        }
+        Ok(())
+    }
+}
+
+impl MmbZigbeeModemProtocol {
+    // Declares one of the host endpoints, so remote devices can discover and bind to it
+    pub fn register_endpoint(&mut self, descriptor: &SimpleDescriptor) -> Result<(), MmbZigbeeModemError> {
+        // Endpoint 0 is the ZDO, 241 and up are reserved
+        if descriptor.endpoint == 0 || descriptor.endpoint > 240 {
+            return Err(MmbZigbeeModemError::new("Add Endpoint: Endpoints go from 1 to 240"));
+        }
+        let mut payload = PayloadWriter::new();
+        descriptor.write(&mut payload)?;
+        self.execute(PrimaryHeader::ZIGBEE_SUPPORT_CONFIG_HEADER,
+            SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::ADD_ENDPOINT),
+            &payload.into_vec(), "Add Endpoint status")
+    }
+
+    // Reads back the simple descriptors of every endpoint registered on the module
+    pub fn registered_endpoints(&mut self) -> Result<Vec<SimpleDescriptor>, MmbZigbeeModemError> {
+        // [Endpoint count: 1][Endpoints: n]
+        let msg = self.transact(PrimaryHeader::ZIGBEE_SUPPORT_CONFIG_HEADER,
+            SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::ENDPOINT_LIST_REQUEST),
+            SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::ENDPOINT_LIST_RESPONSE),
+            &[], "Endpoint List Response")?;
+        let mut reader = PayloadReader::new(&msg.payload);
+        let count = reader.u8()?;
+        let endpoints = reader.bytes(count as usize)?;
+
+        let mut descriptors = Vec::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            let msg = self.transact(PrimaryHeader::ZIGBEE_SUPPORT_CONFIG_HEADER,
+                SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::SIMPLE_DESCRIPTOR_REQUEST),
+                SecondaryHeader::HeaderZigbeeSupportConfig(HeaderZigbeeSupportConfig::SIMPLE_DESCRIPTOR_RESPONSE),
+                &[endpoint], "Simple Descriptor Response")?;
+            descriptors.push(SimpleDescriptor::read(&mut PayloadReader::new(&msg.payload))?);
+        }
+        Ok(descriptors)
+    }
+}
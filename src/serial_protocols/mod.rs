@@ -1,5 +1,37 @@
-//pub mod develco_zigbee_modem_protocol;
-//pub mod mmb_networks_modem_protocol;
+// Opcode and field enums of the serial protocols. Modules send bytes that newer firmware
+// versions added, so the ones we don't know are kept as Unknown(u8) instead of being transmuted.
+// The variants are named as in the protocol specs.
+macro_rules! byte_enum {
+    ($(#[$attr:meta])* $vis:vis enum $name:ident { $($variant:ident = $value:literal),* $(,)* }) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        $vis enum $name {
+            $($variant,)*
+            Unknown(u8)
+        }
+        impl From<u8> for $name {
+            fn from(num: u8) -> $name {
+                match num {
+                    $($value => $name::$variant,)*
+                    _ => {
+                        warn!("Unknown {}: 0x{:02X}", stringify!($name), num);
+                        $name::Unknown(num)
+                    }
+                }
+            }
+        }
+        impl From<$name> for u8 {
+            fn from(value: $name) -> u8 {
+                match value {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(num) => num
+                }
+            }
+        }
+    }
+}
 
+pub mod develco_zigbee_modem_protocol;
 pub mod mmb_networks_modem_protocol;
 pub mod serial_port_parser;
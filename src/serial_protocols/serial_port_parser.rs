@@ -1,13 +1,21 @@
 use std::fmt;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::time::Duration;
 use zigbee_serial_port::ZigbeeSerialPort;
 
 pub trait SerialPortParser{
 	// type Message: fmt::Debug;
 	// type Error: fmt::Debug;
 	//fn parse(&self, buff : &[u8]) -> Result<Self::Message, Self::Error>;
-	fn parse(&self, buff : &[u8]) -> Result<(),()>;
+	// What the parser reports to the application, see ZigbeeModem::run()
+	type Event: fmt::Debug;
+	#[allow(clippy::result_unit_err)]
+	fn parse(&mut self, buff : &[u8]) -> Result<(),()>;
+	fn poll_event(&mut self) -> Option<Self::Event>;
 	fn set_serial_port(&mut self, serial_port: Rc<RefCell<ZigbeeSerialPort>>);
+	// How long the event loop may sleep waiting for data before calling on_timeout()
+	fn next_timeout(&self) -> Option<Duration> { None }
+	fn on_timeout(&mut self) {}
 
 }
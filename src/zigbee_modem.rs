@@ -1,15 +1,10 @@
 extern crate serial;
 
-use std::process::*;
 use std::*;
-use std::os::unix::io::*;
 use mio::*;
 use mio::unix::EventedFd;
-use serial::SerialPort;
 use std::io::Read;
-use std::io::Write;
 use serial_protocols::serial_port_parser::SerialPortParser;
-use std::rc::Weak;
 use std::rc::Rc;
 use std::cell::RefCell;
 use zigbee_serial_port::ZigbeeSerialPort;
@@ -28,20 +23,23 @@ impl <T: SerialPortParser> ZigbeeModem<T> {
         ZigbeeModem{
             serial_port: Rc::new(RefCell::new(serial_port)),
 			token: Token(1),
-            parser: parser,
-            poll: Poll::new().ok().expect("Error creating the event loop!!")
+            parser,
+            poll: Poll::new().expect("Error creating the event loop!!")
 		}
 
 	}
 
-	pub fn run(&self){
+	// Runs the event loop forever. The events the parser reports are handed to `on_event` as
+	// soon as the frames that caused them are processed, along with the parser itself so the
+	// callback can answer them.
+	pub fn run<F>(&mut self, mut on_event: F) where F: FnMut(&mut T, T::Event) {
 		trace!("Starting...");
         {
-            let ref ref_fd = *self.serial_port.borrow();
+            let ref_fd = &*self.serial_port.borrow();
             let fd = ref_fd.get_fd();
     		let evented_fd = EventedFd(&fd);
             trace!("{:?}", evented_fd);
-    		self.poll.register(&evented_fd, self.token, Ready::readable(), PollOpt::edge())
+    		self.poll.register(&evented_fd, self.token, Ready::readable(), PollOpt::level())
                 .unwrap_or_else(|err|{
                     error!("Error registering modem device!. Error {}", err);
     		});
@@ -50,13 +48,12 @@ impl <T: SerialPortParser> ZigbeeModem<T> {
 		let mut events = Events::with_capacity(1024);
 
     	loop{
-        	self.poll.poll(&mut events, None).unwrap();
+        	self.poll.poll(&mut events, self.parser.next_timeout()).unwrap();
         	for event in events.iter() {
         		match event.token() {
         			Token(1) => {
-        				match self.on_incoming_data() {
-                            Err(e) => println!("Warning: Error parsing message: {:?}. Keep going...", e),
-                            _ => {}
+        				if let Err(e) = self.on_incoming_data() {
+                            println!("Warning: Error parsing message: {:?}. Keep going...", e);
                         }
         			}
         			_ => {
@@ -65,33 +62,41 @@ impl <T: SerialPortParser> ZigbeeModem<T> {
         			}
         		}
         	}
+            self.parser.on_timeout();
+            self.dispatch_events(&mut on_event);
     	}
     }
 
-    fn parse(&self, buff: &[u8]) -> Result<(),()>{
-        let msg = self.parser.parse(buff);
-        trace!("Msg received: {:?}", msg.unwrap());
-        Ok(())
+    fn dispatch_events<F>(&mut self, on_event: &mut F) where F: FnMut(&mut T, T::Event) {
+        while let Some(event) = self.parser.poll_event() {
+            on_event(&mut self.parser, event);
+        }
+    }
+
+    pub fn parser_mut(&mut self) -> &mut T {
+        &mut self.parser
     }
 
-	fn on_incoming_data(&self) -> Result<(),()> {
+    fn parse(&mut self, buff: &[u8]) -> Result<(),()>{
+        self.parser.parse(buff)
+    }
+
+	fn on_incoming_data(&mut self) -> Result<(),()> {
 		trace!("Got data from the modem");
 	    let mut buff: Vec<u8> = vec![0;256];
-        let mut _serial_port = self.serial_port.borrow_mut();
-		match _serial_port.read(&mut buff[..]){
+		let size = match self.serial_port.borrow_mut().read(&mut buff[..]){
             Err(ref e) => {
                 error!("Couldn't read from the serial port!!. Error = {}", e);
-                Err(())
+                return Err(());
             },
             Ok(0) => {
                 error!("Serial port closed!");
-                Err(())
+                return Err(());
             },
-            Ok(size) => {
-                trace!("{} bytes read!", size);
-                self.parse(&buff[0..size])
-            }
-        }
+            Ok(size) => size
+        };
+        trace!("{} bytes read!", size);
+        self.parse(&buff[0..size])
 	}
 
 	fn on_error(&self) {
@@ -5,14 +5,10 @@ use std::os::unix::io::*;
 use std::io::Read;
 use std::io::Write;
 use std::io;
-use mio::*;
-use mio::unix::EventedFd;
 use serial::posix::TTYPort;
 use serial::SerialPort;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::cell::Ref;
-use libc;
 
 const SETTINGS: serial::PortSettings = serial::PortSettings {
     baud_rate:    serial::Baud115200,